@@ -33,8 +33,8 @@ fn demo(filename: &Path) {
     let format = AUDIO_S16LSB; // signed 16 bit samples, in little-endian byte order
     let channels = 2; // Stereo
     let chunk_size = 1024;
-    let _ = sdl2_mixer::open_audio(frequency, format, channels, chunk_size).unwrap();
-    sdl2_mixer::allocate_channels(0);
+    let device = sdl2_mixer::open_audio(frequency, format, channels, chunk_size).unwrap();
+    device.allocate_channels(0);
 
     {
         let n = sdl2_mixer::get_chunk_decoders_number();
@@ -52,33 +52,29 @@ fn demo(filename: &Path) {
         }
     }
 
-    println!("query spec => {:?}", sdl2_mixer::query_spec());
+    println!("audio device => {:?}", device);
 
 
-    let music = sdl2_mixer::Music::from_file(filename).unwrap();
+    let music = device.load_music(filename).unwrap();
 
-    fn hook_finished() {
-        println!("play ends! from rust cb");
-    }
-
-    sdl2_mixer::Music::hook_finished(hook_finished);
+    sdl2_mixer::Music::hook_finished(Box::new(|| println!("play ends! from rust cb")));
 
     println!("music => {:?}", music);
     println!("music type => {:?}", music.get_type());
-    println!("music volume => {:?}", sdl2_mixer::Music::get_volume());
-    println!("play => {:?}", music.play(1));
+    println!("music volume => {:?}", device.music().get_volume());
+    println!("play => {:?}", device.music().play(&music, 1));
 
     timer.delay(10000);
 
-    println!("fading out ... {:?}", sdl2_mixer::Music::fade_out(4000));
+    println!("fading out ... {:?}", device.music().fade_out(4000));
 
     timer.delay(5000);
 
     println!("fading in from pos ... {:?}",
-             music.fade_in_from_pos(1, 10000, 100.0));
+             device.music().fade_in_from_pos(&music, 1, 10000, 100.0));
 
     timer.delay(5000);
-    sdl2_mixer::Music::halt();
+    device.music().halt();
     timer.delay(1000);
 
 
@@ -0,0 +1,704 @@
+//! Resolve logical sound names through directories, archives and overlays.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use sdl2_mixer::assets::{AssetSource, DirSource, Overlay, ZipSource};
+//!
+//! // Mods are searched before the base game.
+//! let sounds = Overlay::new()
+//!     .with_layer(ZipSource::open(Path::new("mods/louder.zip")).unwrap())
+//!     .with_layer(DirSource::new(Path::new("data/sounds")));
+//! let (chunk, origin) = sounds.load_chunk("ui/click.wav").unwrap();
+//! println!("click.wav from {}", origin);
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use inflate::inflate;
+use {Chunk, MixerError, Music};
+
+trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// An asset opened from an `AssetSource`.
+pub struct Asset {
+    reader: Box<dyn ReadSeek>,
+    origin: String,
+}
+
+impl Asset {
+    /// Wrap a reader over the asset's content.
+    pub fn new<R: Read + Seek + Send + 'static>(reader: R, origin: String) -> Asset {
+        Asset {
+            reader: Box::new(reader),
+            origin,
+        }
+    }
+
+    /// Where the asset was found, e.g. the file or archive entry.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+}
+
+impl Read for Asset {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for Asset {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+/// A place sound assets are looked up in by logical name, a `/` separated
+/// relative path like `"ui/click.wav"`.
+pub trait AssetSource: Send + Sync {
+    /// Open the named asset, or `None` if this source doesn't have it.
+    fn open(&self, name: &str) -> io::Result<Option<Asset>>;
+
+    /// Load the named asset as a sample, along with where it was found.
+    fn load_chunk(&self, name: &str) -> Result<(Chunk, String), MixerError> {
+        let asset = open_existing(self, name)?;
+        let origin = asset.origin().to_owned();
+        let chunk = Chunk::from_reader(asset).map_err(|e| e.for_path(Path::new(&origin)))?;
+        Ok((chunk, origin))
+    }
+
+    /// Load the named asset as music, along with where it was found.
+    fn load_music(&self, name: &str) -> Result<(Music, String), MixerError> {
+        let asset = open_existing(self, name)?;
+        let origin = asset.origin().to_owned();
+        let music = Music::from_reader(asset).map_err(|e| e.for_path(Path::new(&origin)))?;
+        Ok((music, origin))
+    }
+}
+
+fn open_existing<S: AssetSource + ?Sized>(source: &S, name: &str) -> Result<Asset, MixerError> {
+    match source.open(name) {
+        Ok(Some(asset)) => Ok(asset),
+        Ok(None) => Err(MixerError::NotFound(name.to_owned())),
+        Err(e) => {
+            Err(MixerError::Load {
+                path: PathBuf::from(name),
+                reason: e.to_string(),
+            })
+        }
+    }
+}
+
+/// Turn a logical name into a relative path, refusing to leave the source.
+fn relative_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        Some(path.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Normalize an archive entry or logical name, e.g. `./sfx\a.wav` to `sfx/a.wav`.
+fn entry_name(name: &str) -> String {
+    name.replace('\\', "/")
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Assets stored as files below a directory.
+#[derive(Debug, Clone)]
+pub struct DirSource {
+    root: PathBuf,
+}
+
+impl DirSource {
+    pub fn new(root: &Path) -> DirSource {
+        DirSource { root: root.to_owned() }
+    }
+
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        relative_path(name).map(|path| self.root.join(path)).filter(|path| path.is_file())
+    }
+}
+
+impl AssetSource for DirSource {
+    fn open(&self, name: &str) -> io::Result<Option<Asset>> {
+        match self.find(name) {
+            Some(path) => {
+                let origin = path.display().to_string();
+                Ok(Some(Asset::new(File::open(path)?, origin)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn load_chunk(&self, name: &str) -> Result<(Chunk, String), MixerError> {
+        let path = self.find(name).ok_or_else(|| MixerError::NotFound(name.to_owned()))?;
+        Ok((Chunk::from_file(&path)?, path.display().to_string()))
+    }
+
+    fn load_music(&self, name: &str) -> Result<(Music, String), MixerError> {
+        let path = self.find(name).ok_or_else(|| MixerError::NotFound(name.to_owned()))?;
+        Ok((Music::from_file(&path)?, path.display().to_string()))
+    }
+}
+
+/// A window into a file, for archive entries stored as they are.
+struct FileSlice {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl FileSlice {
+    fn new(path: &Path, start: u64, len: u64) -> io::Result<FileSlice> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(FileSlice {
+            file,
+            start,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for FileSlice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = (self.len - self.pos) as usize;
+        let max = if buf.len() < left { buf.len() } else { left };
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for FileSlice {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+            SeekFrom::End(offset) => self.len as i64 + offset,
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start"));
+        }
+        // Like a file, seeking past the end is allowed and reads nothing.
+        let target = if target as u64 > self.len { self.len } else { target as u64 };
+        self.file.seek(SeekFrom::Start(self.start + target))?;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+fn invalid_archive(path: &Path, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("{}: {}", path.display(), msg))
+}
+
+/// Parse an octal number field of a tar header.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    let digits = field.iter()
+                      .cloned()
+                      .skip_while(|&b| b == b' ')
+                      .take_while(|b| (b'0'..=b'7').contains(b));
+    let mut value: u64 = 0;
+    for digit in digits {
+        value = value.checked_mul(8)?.checked_add((digit - b'0') as u64)?;
+    }
+    Some(value)
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Assets stored in an uncompressed tar archive.
+#[derive(Debug, Clone)]
+pub struct TarSource {
+    path: PathBuf,
+    entries: HashMap<String, (u64, u64)>,
+}
+
+// Longer GNU long names are surely not an asset name.
+const MAX_LONG_NAME: u64 = 64 * 1024;
+
+/// Index the regular files of a tar archive as their offset and size.
+fn tar_entries<R: Read + Seek>(file: &mut R,
+                               path: &Path)
+                               -> io::Result<HashMap<String, (u64, u64)>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut entries = HashMap::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    let mut header = [0u8; 512];
+    loop {
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            // Archives truncated after the last entry are common enough.
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        offset += 512;
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_number(&header[124..136])
+                       .ok_or_else(|| invalid_archive(path, "bad entry size"))?;
+        if size > len - offset {
+            return Err(invalid_archive(path, "entry exceeds the archive"));
+        }
+        let padded = (size + 511) / 512 * 512;
+        match header[156] {
+            // GNU long name of the next entry.
+            b'L' => {
+                if size > MAX_LONG_NAME {
+                    return Err(invalid_archive(path, "entry name too long"));
+                }
+                let mut name = vec![0; size as usize];
+                file.read_exact(&mut name)?;
+                file.seek(SeekFrom::Current((padded - size) as i64))?;
+                long_name = Some(tar_string(&name));
+            }
+            b'0' | 0 => {
+                let name = match long_name.take() {
+                    Some(name) => name,
+                    None if &header[257..262] == b"ustar" && header[345] != 0 => {
+                        format!("{}/{}",
+                                tar_string(&header[345..500]),
+                                tar_string(&header[0..100]))
+                    }
+                    None => tar_string(&header[0..100]),
+                };
+                entries.insert(entry_name(&name), (offset, size));
+                file.seek(SeekFrom::Current(padded as i64))?;
+            }
+            _ => {
+                long_name = None;
+                file.seek(SeekFrom::Current(padded as i64))?;
+            }
+        }
+        offset += padded;
+    }
+    Ok(entries)
+}
+
+impl TarSource {
+    /// Index the regular files of the archive at `path`.
+    pub fn open(path: &Path) -> io::Result<TarSource> {
+        let mut file = File::open(path)?;
+        Ok(TarSource {
+            entries: tar_entries(&mut file, path)?,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl AssetSource for TarSource {
+    fn open(&self, name: &str) -> io::Result<Option<Asset>> {
+        let name = entry_name(name);
+        match self.entries.get(&name) {
+            Some(&(start, len)) => {
+                let origin = format!("{}:{}", self.path.display(), name);
+                Ok(Some(Asset::new(FileSlice::new(&self.path, start, len)?, origin)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn le16(buf: &[u8], at: usize) -> u64 {
+    buf[at] as u64 | (buf[at + 1] as u64) << 8
+}
+
+fn le32(buf: &[u8], at: usize) -> u64 {
+    le16(buf, at) | le16(buf, at + 2) << 16
+}
+
+#[derive(Debug, Clone)]
+struct ZipEntry {
+    method: u16,
+    header_offset: u64,
+    compressed_size: u64,
+    size: u64,
+}
+
+/// Assets stored in a zip archive, either stored or deflated.
+#[derive(Debug, Clone)]
+pub struct ZipSource {
+    path: PathBuf,
+    entries: HashMap<String, ZipEntry>,
+}
+
+/// Index the files of a zip archive.
+fn zip_entries<R: Read + Seek>(file: &mut R, path: &Path) -> io::Result<HashMap<String, ZipEntry>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // The end of central directory record is followed by a comment of up to 64KiB.
+    let tail_len = if len < 22 + 0xFFFF { len } else { 22 + 0xFFFF };
+    let mut tail = vec![0; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+                   .rev()
+                   .find(|&i| &tail[i..i + 4] == b"PK\x05\x06")
+                   .ok_or_else(|| invalid_archive(path, "not a zip archive"))?;
+    let count = le16(&tail, eocd + 10);
+    let dir_size = le32(&tail, eocd + 12);
+    let dir_offset = le32(&tail, eocd + 16);
+    if count == 0xFFFF || dir_offset == 0xFFFF_FFFF {
+        return Err(invalid_archive(path, "zip64 archives are not supported"));
+    }
+    // The central directory comes right before its end record.
+    if dir_offset + dir_size > len - tail_len + eocd as u64 {
+        return Err(invalid_archive(path, "bad central directory"));
+    }
+
+    let mut dir = vec![0; dir_size as usize];
+    file.seek(SeekFrom::Start(dir_offset))?;
+    file.read_exact(&mut dir)?;
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + 46 > dir.len() || &dir[pos..pos + 4] != b"PK\x01\x02" {
+            return Err(invalid_archive(path, "bad central directory"));
+        }
+        let name_len = le16(&dir, pos + 28) as usize;
+        let extra_len = le16(&dir, pos + 30) as usize;
+        let comment_len = le16(&dir, pos + 32) as usize;
+        let name_end = pos + 46 + name_len;
+        if name_end > dir.len() {
+            return Err(invalid_archive(path, "bad central directory"));
+        }
+        let name = String::from_utf8_lossy(&dir[pos + 46..name_end]).into_owned();
+        if !name.ends_with('/') {
+            entries.insert(entry_name(&name),
+                           ZipEntry {
+                               method: le16(&dir, pos + 10) as u16,
+                               compressed_size: le32(&dir, pos + 20),
+                               size: le32(&dir, pos + 24),
+                               header_offset: le32(&dir, pos + 42),
+                           });
+        }
+        pos = name_end + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+impl ZipSource {
+    /// Index the files of the archive at `path`.
+    pub fn open(path: &Path) -> io::Result<ZipSource> {
+        let mut file = File::open(path)?;
+        Ok(ZipSource {
+            entries: zip_entries(&mut file, path)?,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl AssetSource for ZipSource {
+    fn open(&self, name: &str) -> io::Result<Option<Asset>> {
+        let name = entry_name(name);
+        let entry = match self.entries.get(&name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+        let mut header = [0u8; 30];
+        file.seek(SeekFrom::Start(entry.header_offset))?;
+        file.read_exact(&mut header)?;
+        if &header[0..4] != b"PK\x03\x04" {
+            return Err(invalid_archive(&self.path, "bad local header"));
+        }
+        let start = entry.header_offset + 30 + le16(&header, 26) + le16(&header, 28);
+        if start + entry.compressed_size > len {
+            return Err(invalid_archive(&self.path,
+                                       &format!("{}: entry exceeds the archive", name)));
+        }
+        let origin = format!("{}:{}", self.path.display(), name);
+        match entry.method {
+            0 if entry.size != entry.compressed_size => {
+                Err(invalid_archive(&self.path, &format!("{}: bad stored entry size", name)))
+            }
+            0 => Ok(Some(Asset::new(FileSlice::new(&self.path, start, entry.size)?, origin))),
+            8 => {
+                let mut compressed = vec![0; entry.compressed_size as usize];
+                file.seek(SeekFrom::Start(start))?;
+                file.read_exact(&mut compressed)?;
+                let data = inflate(&compressed, entry.size as usize)?;
+                Ok(Some(Asset::new(Cursor::new(data), origin)))
+            }
+            method => {
+                Err(invalid_archive(&self.path,
+                                    &format!("{}: unsupported compression method {}",
+                                             name,
+                                             method)))
+            }
+        }
+    }
+}
+
+/// Several sources searched in order, so the first one having an asset
+/// supplies it. Put mods before the base game to let them override its sounds.
+#[derive(Default)]
+pub struct Overlay {
+    layers: Vec<Box<dyn AssetSource>>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay { layers: Vec::new() }
+    }
+
+    /// Add a layer, searched after the ones already added.
+    pub fn with_layer<S: AssetSource + 'static>(mut self, source: S) -> Overlay {
+        self.push(source);
+        self
+    }
+
+    /// Add a layer, searched after the ones already added.
+    pub fn push<S: AssetSource + 'static>(&mut self, source: S) {
+        self.layers.push(Box::new(source));
+    }
+}
+
+impl AssetSource for Overlay {
+    fn open(&self, name: &str) -> io::Result<Option<Asset>> {
+        for layer in &self.layers {
+            if let Some(asset) = layer.open(name)? {
+                return Ok(Some(asset));
+            }
+        }
+        Ok(None)
+    }
+
+    fn load_chunk(&self, name: &str) -> Result<(Chunk, String), MixerError> {
+        for layer in &self.layers {
+            match layer.load_chunk(name) {
+                Err(MixerError::NotFound(_)) => {}
+                loaded => return loaded,
+            }
+        }
+        Err(MixerError::NotFound(name.to_owned()))
+    }
+
+    fn load_music(&self, name: &str) -> Result<(Music, String), MixerError> {
+        for layer in &self.layers {
+            match layer.load_music(name) {
+                Err(MixerError::NotFound(_)) => {}
+                loaded => return loaded,
+            }
+        }
+        Err(MixerError::NotFound(name.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Cursor, Read, Write};
+    use std::path::Path;
+    use std::process;
+    use super::{tar_entries, zip_entries, AssetSource, ZipSource};
+
+    fn tar_header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    fn tar_entry(tar: &mut Vec<u8>, name: &str, data: &[u8], kind: u8) {
+        tar.extend(tar_header(name, data.len(), kind));
+        tar.extend_from_slice(data);
+        let padded = (data.len() + 511) / 512 * 512;
+        tar.resize(tar.len() + padded - data.len(), 0);
+    }
+
+    fn tar(tar: Vec<u8>) -> Cursor<Vec<u8>> {
+        Cursor::new(tar)
+    }
+
+    #[test]
+    fn tar_indexes_regular_files() {
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, "sfx/", b"", b'5');
+        tar_entry(&mut archive, "sfx/a.wav", b"aaa", b'0');
+        tar_entry(&mut archive, "./b.wav", &[1; 600], 0);
+        let mut prefixed = tar_header("c.wav", 1, b'0');
+        prefixed[345..350].copy_from_slice(b"music");
+        archive.extend(prefixed);
+        archive.extend_from_slice(&[0; 512]);
+        tar_entry(&mut archive, "././@LongLink", b"long/name.wav\0", b'L');
+        tar_entry(&mut archive, "long/na", b"dd", b'0');
+        archive.extend_from_slice(&[0; 1024]);
+        tar_entry(&mut archive, "after/the/end.wav", b"e", b'0');
+
+        let entries = tar_entries(&mut tar(archive), Path::new("test.tar")).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries["sfx/a.wav"], (1024, 3));
+        assert_eq!(entries["b.wav"], (2048, 600));
+        assert_eq!(entries["music/c.wav"], (3584, 1));
+        assert_eq!(entries["long/name.wav"], (5632, 2));
+    }
+
+    #[test]
+    fn tar_may_end_without_end_blocks() {
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, "a.wav", b"a", b'0');
+        let entries = tar_entries(&mut tar(archive), Path::new("test.tar")).unwrap();
+        assert_eq!(entries["a.wav"], (512, 1));
+    }
+
+    #[test]
+    fn tar_sizes_are_bounded() {
+        let mut archive = tar_header("a.wav", 513, b'0');
+        archive.extend_from_slice(&[0; 512]);
+        assert!(tar_entries(&mut tar(archive), Path::new("test.tar")).is_err());
+
+        let mut archive = tar_header("a.wav", 0, b'0');
+        archive[124..136].copy_from_slice(b"77777777777\0");
+        assert!(tar_entries(&mut tar(archive), Path::new("test.tar")).is_err());
+
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, "././@LongLink", &vec![b'a'; 65 * 1024], b'L');
+        assert!(tar_entries(&mut tar(archive), Path::new("test.tar")).is_err());
+    }
+
+    fn le16(out: &mut Vec<u8>, value: usize) {
+        out.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+
+    fn le32(out: &mut Vec<u8>, value: usize) {
+        le16(out, value & 0xFFFF);
+        le16(out, value >> 16);
+    }
+
+    /// A zip archive of (name, method, size, data) entries.
+    fn zip(files: &[(&str, u16, usize, &[u8])]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut dir = Vec::new();
+        for &(name, method, size, data) in files {
+            let offset = zip.len();
+            zip.extend_from_slice(b"PK\x03\x04");
+            le16(&mut zip, 20);
+            le16(&mut zip, 0);
+            le16(&mut zip, method as usize);
+            le32(&mut zip, 0);
+            le32(&mut zip, 0);
+            le32(&mut zip, data.len());
+            le32(&mut zip, size);
+            le16(&mut zip, name.len());
+            le16(&mut zip, 0);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(data);
+
+            dir.extend_from_slice(b"PK\x01\x02");
+            le16(&mut dir, 20);
+            le16(&mut dir, 20);
+            le16(&mut dir, 0);
+            le16(&mut dir, method as usize);
+            le32(&mut dir, 0);
+            le32(&mut dir, 0);
+            le32(&mut dir, data.len());
+            le32(&mut dir, size);
+            le16(&mut dir, name.len());
+            le16(&mut dir, 0);
+            le16(&mut dir, 0);
+            le16(&mut dir, 0);
+            le16(&mut dir, 0);
+            le32(&mut dir, 0);
+            le32(&mut dir, offset);
+            dir.extend_from_slice(name.as_bytes());
+        }
+        let dir_offset = zip.len();
+        zip.extend_from_slice(&dir);
+        zip.extend_from_slice(b"PK\x05\x06");
+        le16(&mut zip, 0);
+        le16(&mut zip, 0);
+        le16(&mut zip, files.len());
+        le16(&mut zip, files.len());
+        le32(&mut zip, dir.len());
+        le32(&mut zip, dir_offset);
+        le16(&mut zip, 4);
+        zip.extend_from_slice(b"note");
+        zip
+    }
+
+    // "Hello, hello, hello!" deflated by zlib.
+    const DEFLATED: [u8; 12] = [243, 72, 205, 201, 201, 215, 81, 200, 64, 162, 20, 1];
+
+    #[test]
+    fn zip_indexes_files() {
+        let archive = zip(&[("sfx/", 0, 0, b""),
+                            ("sfx\\a.wav", 0, 3, b"aaa"),
+                            ("b.wav", 8, 20, &DEFLATED)]);
+        let entries = zip_entries(&mut Cursor::new(archive), Path::new("test.zip")).unwrap();
+        assert_eq!(entries.len(), 2);
+        let a = &entries["sfx/a.wav"];
+        assert_eq!((a.method, a.header_offset, a.compressed_size, a.size), (0, 34, 3, 3));
+        let b = &entries["b.wav"];
+        assert_eq!((b.method, b.header_offset, b.compressed_size, b.size), (8, 76, 12, 20));
+    }
+
+    #[test]
+    fn zip_directory_is_bounded() {
+        let path = Path::new("test.zip");
+        assert!(zip_entries(&mut Cursor::new(b"PK\x03\x04".to_vec()), path).is_err());
+
+        let archive = zip(&[("a.wav", 0, 3, b"aaa")]);
+        let end = archive.len() - 22 - 4;
+        let mut bad = archive.clone();
+        bad[end + 12] = 0xFF;
+        assert!(zip_entries(&mut Cursor::new(bad), path).is_err());
+        let mut bad = archive.clone();
+        bad[end + 16] = 0xFF;
+        assert!(zip_entries(&mut Cursor::new(bad), path).is_err());
+        let mut bad = archive.clone();
+        bad[end + 10] = 2;
+        assert!(zip_entries(&mut Cursor::new(bad), path).is_err());
+        let mut zip64 = archive.clone();
+        zip64[end + 10] = 0xFF;
+        zip64[end + 11] = 0xFF;
+        assert!(zip_entries(&mut Cursor::new(zip64), path).is_err());
+    }
+
+    fn read(source: &ZipSource, name: &str) -> Result<Vec<u8>, String> {
+        let mut asset = source.open(name).map_err(|e| e.to_string())?.unwrap();
+        let mut data = Vec::new();
+        asset.read_to_end(&mut data).unwrap();
+        Ok(data)
+    }
+
+    #[test]
+    fn zip_source_checks_entry_sizes() {
+        let archive = zip(&[("stored.wav", 0, 3, b"aaa"),
+                            ("deflated.wav", 8, 20, &DEFLATED),
+                            ("bomb.wav", 8, 19, &DEFLATED),
+                            ("odd.wav", 0, 4, b"aaa")]);
+        let path = env::temp_dir().join(format!("sdl2_mixer-assets-{}.zip", process::id()));
+        File::create(&path).unwrap().write_all(&archive).unwrap();
+        let mut source = ZipSource::open(&path).unwrap();
+        assert_eq!(read(&source, "stored.wav").unwrap(), b"aaa");
+        assert_eq!(read(&source, "deflated.wav").unwrap(), b"Hello, hello, hello!");
+        assert!(read(&source, "bomb.wav").unwrap_err().contains("more output than expected"));
+        assert!(read(&source, "odd.wav").unwrap_err().contains("bad stored entry size"));
+        assert!(source.open("missing.wav").unwrap().is_none());
+        source.entries.get_mut("deflated.wav").unwrap().compressed_size = 1 << 20;
+        assert!(read(&source, "deflated.wav").unwrap_err().contains("entry exceeds the archive"));
+        fs::remove_file(&path).unwrap();
+    }
+}
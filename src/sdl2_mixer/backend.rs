@@ -0,0 +1,660 @@
+//! The mixer implementation the crate's wrappers call into.
+//!
+//! `SdlBackend`, calling SDL_mixer, is used unless another backend is
+//! installed with `set`. With the `testing` feature, `fake::FakeMixer`
+//! installs a pure Rust backend for unit tests.
+//!
+//! Every SDL_mixer function the crate calls goes through the backend, as
+//! do the SDL error, the audio lock and mixing of hooked music. Only the
+//! headless driver of the `testing` module and `EventSink::Sdl` call SDL
+//! itself.
+//!
+//! Without the default `native` feature SDL isn't linked and there is no
+//! `SdlBackend`, so a backend must be set before the mixer is used.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, RwLock};
+use libc::{c_char, c_int, c_void};
+#[cfg(feature = "native")]
+use libc::{c_uint, c_double};
+#[cfg(feature = "native")]
+use sdl2;
+
+use {Fading, MusicType, Version};
+#[cfg(feature = "native")]
+use ffi::{self, Mix_Music};
+
+pub use ffi::{Mix_Chunk, Mix_EffectDone_t, Mix_EffectFunc_t, SDL_RWops};
+
+/// A callback given a buffer of the whole mixed stream, as taken by
+/// `Mix_HookMusic` and `Mix_SetPostMix`.
+pub type MixFunc = unsafe extern "C" fn(*mut c_void, *mut u8, c_int);
+
+/// A callback given each soundfont path, as taken by `Mix_EachSoundFont`.
+pub type SoundFontFunc = unsafe extern "C" fn(*const c_char, *mut c_void) -> c_int;
+
+/// Music loaded by a backend, which only that backend can make sense of: a
+/// `Mix_Music` pointer for `SdlBackend`. A null handle is a failure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MusicHandle(*mut c_void);
+
+impl MusicHandle {
+    pub fn new(ptr: *mut c_void) -> MusicHandle {
+        MusicHandle(ptr)
+    }
+
+    pub fn null() -> MusicHandle {
+        MusicHandle(ptr::null_mut())
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+
+    pub fn as_ptr(self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// The SDL_mixer functions the wrappers go through.
+///
+/// Each method mirrors the SDL_mixer function of the same name, taking
+/// and returning the same values. Failures set the error read back with
+/// `get_error`.
+pub trait Backend: Send + Sync + fmt::Debug {
+    fn linked_version(&self) -> Version;
+    /// The flags of the decoders loaded, of those in `flags`.
+    fn init(&self, flags: u32) -> u32;
+    fn quit(&self);
+    fn get_num_chunk_decoders(&self) -> isize;
+    /// `None` if there is no decoder at `index`.
+    fn get_chunk_decoder(&self, index: isize) -> Option<String>;
+    fn get_num_music_decoders(&self) -> isize;
+    /// `None` if there is no decoder at `index`.
+    fn get_music_decoder(&self, index: isize) -> Option<String>;
+
+    /// The message of the last failure on this thread, as `sdl2::get_error`.
+    fn get_error(&self) -> String;
+    fn set_error(&self, msg: &str);
+    /// Keep the mixing callback from running until `unlock_audio`, which
+    /// must be called as many times on the same thread.
+    fn lock_audio(&self);
+    fn unlock_audio(&self);
+    /// Mix `src` into `dst`, both samples of the raw `format`, at `volume`
+    /// between 0 and 128, as `SDL_MixAudioFormat`.
+    fn mix_audio_format(&self, dst: &mut [u8], src: &[u8], format: u16, volume: isize);
+
+    fn open_audio(&self, frequency: isize, format: u16, channels: isize, chunk_size: isize) -> isize;
+    fn close_audio(&self);
+    /// The frequency, raw format and channels, or `None` if audio isn't open.
+    fn query_spec(&self) -> Option<(isize, u16, isize)>;
+
+    /// # Safety
+    ///
+    /// `src` must be a valid `SDL_RWops`.
+    unsafe fn load_wav_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> *mut Mix_Chunk;
+    /// # Safety
+    ///
+    /// `mem` must hold a whole WAV file and outlive the chunk.
+    unsafe fn quick_load_wav(&self, mem: *mut u8) -> *mut Mix_Chunk;
+    /// # Safety
+    ///
+    /// `mem` must hold `len` bytes and outlive the chunk.
+    unsafe fn quick_load_raw(&self, mem: *mut u8, len: u32) -> *mut Mix_Chunk;
+    /// # Safety
+    ///
+    /// `chunk` must come from this backend, and isn't used afterwards.
+    unsafe fn free_chunk(&self, chunk: *mut Mix_Chunk);
+    /// # Safety
+    ///
+    /// `chunk` must come from this backend and not be freed.
+    unsafe fn volume_chunk(&self, chunk: *mut Mix_Chunk, volume: isize) -> isize;
+
+    fn allocate_channels(&self, numchans: isize) -> isize;
+    fn volume(&self, channel: isize, volume: isize) -> isize;
+    /// # Safety
+    ///
+    /// `chunk` must come from this backend and outlive its playback.
+    unsafe fn play_channel_timed(&self,
+                                 channel: isize,
+                                 chunk: *mut Mix_Chunk,
+                                 loops: isize,
+                                 ticks: isize)
+                                 -> isize;
+    /// # Safety
+    ///
+    /// `chunk` must come from this backend and outlive its playback.
+    unsafe fn fade_in_channel_timed(&self,
+                                    channel: isize,
+                                    chunk: *mut Mix_Chunk,
+                                    loops: isize,
+                                    ms: isize,
+                                    ticks: isize)
+                                    -> isize;
+    fn pause(&self, channel: isize);
+    fn resume(&self, channel: isize);
+    fn halt_channel(&self, channel: isize);
+    fn expire_channel(&self, channel: isize, ticks: isize) -> isize;
+    fn fade_out_channel(&self, channel: isize, ms: isize) -> isize;
+    fn playing(&self, channel: isize) -> isize;
+    fn paused(&self, channel: isize) -> isize;
+    fn fading_channel(&self, channel: isize) -> Fading;
+    fn channel_finished(&self, callback: Option<extern "C" fn(c_int)>);
+
+    fn set_panning(&self, channel: isize, left: u8, right: u8) -> bool;
+    fn set_distance(&self, channel: isize, distance: u8) -> bool;
+    fn set_position(&self, channel: isize, angle: i16, distance: u8) -> bool;
+    fn set_reverse_stereo(&self, channel: isize, flip: bool) -> bool;
+    fn unregister_all_effects(&self, channel: isize) -> bool;
+    /// # Safety
+    ///
+    /// `arg` must stay valid for `f` and `d` until the effect is unregistered.
+    unsafe fn register_effect(&self,
+                              channel: isize,
+                              f: Mix_EffectFunc_t,
+                              d: Mix_EffectDone_t,
+                              arg: *mut c_void)
+                              -> bool;
+    fn unregister_effect(&self, channel: isize, f: Mix_EffectFunc_t) -> bool;
+    /// # Safety
+    ///
+    /// `arg` must stay valid for `f` until another post-mix callback is set.
+    unsafe fn set_post_mix(&self, f: Option<MixFunc>, arg: *mut c_void);
+
+    fn reserve_channels(&self, num: isize) -> isize;
+    fn group_channel(&self, channel: isize, tag: isize) -> bool;
+    fn group_channels(&self, from: isize, to: isize, tag: isize) -> isize;
+    fn group_count(&self, tag: isize) -> isize;
+    fn group_available(&self, tag: isize) -> isize;
+    fn group_oldest(&self, tag: isize) -> isize;
+    fn group_newer(&self, tag: isize) -> isize;
+    fn fade_out_group(&self, tag: isize, ms: isize) -> isize;
+    fn halt_group(&self, tag: isize);
+
+    fn load_mus(&self, file: &CStr) -> MusicHandle;
+    /// # Safety
+    ///
+    /// `src` must be a valid `SDL_RWops` that outlives the music.
+    unsafe fn load_mus_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> MusicHandle;
+    /// # Safety
+    ///
+    /// `src` must be a valid `SDL_RWops` that outlives the music.
+    unsafe fn load_mus_type_rw(&self,
+                               src: *mut SDL_RWops,
+                               music_type: MusicType,
+                               freesrc: bool)
+                               -> MusicHandle;
+    /// # Safety
+    ///
+    /// `music` must come from this backend, and isn't used afterwards.
+    unsafe fn free_music(&self, music: MusicHandle);
+    /// # Safety
+    ///
+    /// `music` must come from this backend and not be freed.
+    unsafe fn get_music_type(&self, music: MusicHandle) -> MusicType;
+    /// # Safety
+    ///
+    /// `music` must come from this backend and outlive its playback.
+    unsafe fn play_music(&self, music: MusicHandle, loops: isize) -> isize;
+    /// # Safety
+    ///
+    /// `music` must come from this backend and outlive its playback.
+    unsafe fn fade_in_music_pos(&self,
+                                music: MusicHandle,
+                                loops: isize,
+                                ms: isize,
+                                position: f64)
+                                -> isize;
+    fn volume_music(&self, volume: isize) -> isize;
+    fn pause_music(&self);
+    fn resume_music(&self);
+    fn rewind_music(&self);
+    fn set_music_position(&self, position: f64) -> isize;
+    fn halt_music(&self);
+    fn fade_out_music(&self, ms: isize) -> isize;
+    fn playing_music(&self) -> bool;
+    fn paused_music(&self) -> bool;
+    fn fading_music(&self) -> Fading;
+    fn hook_music_finished(&self, callback: Option<extern "C" fn()>);
+    /// # Safety
+    ///
+    /// `arg` must stay valid for `f` until another music hook is set.
+    unsafe fn hook_music(&self, f: Option<MixFunc>, arg: *mut c_void);
+    fn set_music_cmd(&self, command: &CStr) -> isize;
+    fn set_synchro_value(&self, value: isize) -> isize;
+    fn get_synchro_value(&self) -> isize;
+    /// Paths separated by `;`, or `None` for the default soundfonts.
+    fn set_sound_fonts(&self, paths: Option<&CStr>) -> bool;
+    fn get_sound_fonts(&self) -> Option<String>;
+    /// # Safety
+    ///
+    /// `f` must be safe to call with `data`.
+    unsafe fn each_sound_font(&self, f: SoundFontFunc, data: *mut c_void) -> bool;
+}
+
+/// The backend calling SDL_mixer, used by default.
+#[cfg(feature = "native")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SdlBackend;
+
+#[cfg(feature = "native")]
+unsafe fn c_string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(s).to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(feature = "native")]
+impl Backend for SdlBackend {
+    fn linked_version(&self) -> Version {
+        unsafe { Version::from_ll(*ffi::Mix_Linked_Version()) }
+    }
+
+    fn init(&self, flags: u32) -> u32 {
+        unsafe { ffi::Mix_Init(flags as c_int) as u32 }
+    }
+
+    fn quit(&self) {
+        unsafe { ffi::Mix_Quit() }
+    }
+
+    fn get_num_chunk_decoders(&self) -> isize {
+        unsafe { ffi::Mix_GetNumChunkDecoders() as isize }
+    }
+
+    fn get_chunk_decoder(&self, index: isize) -> Option<String> {
+        unsafe { c_string(ffi::Mix_GetChunkDecoder(index as c_int)) }
+    }
+
+    fn get_num_music_decoders(&self) -> isize {
+        unsafe { ffi::Mix_GetNumMusicDecoders() as isize }
+    }
+
+    fn get_music_decoder(&self, index: isize) -> Option<String> {
+        unsafe { c_string(ffi::Mix_GetMusicDecoder(index as c_int)) }
+    }
+
+    fn get_error(&self) -> String {
+        sdl2::get_error()
+    }
+
+    fn set_error(&self, msg: &str) {
+        // Only fails for messages with a nul byte, which aren't ours.
+        let _ = sdl2::set_error(msg);
+    }
+
+    fn lock_audio(&self) {
+        unsafe { ffi::SDL_LockAudio() }
+    }
+
+    fn unlock_audio(&self) {
+        unsafe { ffi::SDL_UnlockAudio() }
+    }
+
+    fn mix_audio_format(&self, dst: &mut [u8], src: &[u8], format: u16, volume: isize) {
+        let len = dst.len().min(src.len());
+        unsafe {
+            ffi::SDL_MixAudioFormat(dst.as_mut_ptr(),
+                                    src.as_ptr(),
+                                    format,
+                                    len as u32,
+                                    volume as c_int)
+        }
+    }
+
+    fn open_audio(&self, frequency: isize, format: u16, channels: isize, chunk_size: isize) -> isize {
+        unsafe {
+            ffi::Mix_OpenAudio(frequency as c_int, format, channels as c_int, chunk_size as c_int) as isize
+        }
+    }
+
+    fn close_audio(&self) {
+        unsafe { ffi::Mix_CloseAudio() }
+    }
+
+    fn query_spec(&self) -> Option<(isize, u16, isize)> {
+        let mut frequency: c_int = 0;
+        let mut format: u16 = 0;
+        let mut channels: c_int = 0;
+        let ret = unsafe { ffi::Mix_QuerySpec(&mut frequency, &mut format, &mut channels) };
+        if ret == 0 {
+            None
+        } else {
+            Some((frequency as isize, format, channels as isize))
+        }
+    }
+
+    unsafe fn load_wav_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> *mut Mix_Chunk {
+        ffi::Mix_LoadWAV_RW(src, freesrc as c_int)
+    }
+
+    unsafe fn quick_load_wav(&self, mem: *mut u8) -> *mut Mix_Chunk {
+        ffi::Mix_QuickLoad_WAV(mem)
+    }
+
+    unsafe fn quick_load_raw(&self, mem: *mut u8, len: u32) -> *mut Mix_Chunk {
+        ffi::Mix_QuickLoad_RAW(mem, len)
+    }
+
+    unsafe fn free_chunk(&self, chunk: *mut Mix_Chunk) {
+        ffi::Mix_FreeChunk(chunk)
+    }
+
+    unsafe fn volume_chunk(&self, chunk: *mut Mix_Chunk, volume: isize) -> isize {
+        ffi::Mix_VolumeChunk(chunk, volume as c_int) as isize
+    }
+
+    fn allocate_channels(&self, numchans: isize) -> isize {
+        unsafe { ffi::Mix_AllocateChannels(numchans as c_int) as isize }
+    }
+
+    fn volume(&self, channel: isize, volume: isize) -> isize {
+        unsafe { ffi::Mix_Volume(channel as c_int, volume as c_int) as isize }
+    }
+
+    unsafe fn play_channel_timed(&self,
+                                 channel: isize,
+                                 chunk: *mut Mix_Chunk,
+                                 loops: isize,
+                                 ticks: isize)
+                                 -> isize {
+        ffi::Mix_PlayChannelTimed(channel as c_int, chunk, loops as c_int, ticks as c_int) as isize
+    }
+
+    unsafe fn fade_in_channel_timed(&self,
+                                    channel: isize,
+                                    chunk: *mut Mix_Chunk,
+                                    loops: isize,
+                                    ms: isize,
+                                    ticks: isize)
+                                    -> isize {
+        ffi::Mix_FadeInChannelTimed(channel as c_int,
+                                    chunk,
+                                    loops as c_int,
+                                    ms as c_int,
+                                    ticks as c_int) as isize
+    }
+
+    fn pause(&self, channel: isize) {
+        unsafe { ffi::Mix_Pause(channel as c_int) }
+    }
+
+    fn resume(&self, channel: isize) {
+        unsafe { ffi::Mix_Resume(channel as c_int) }
+    }
+
+    fn halt_channel(&self, channel: isize) {
+        unsafe {
+            ffi::Mix_HaltChannel(channel as c_int);
+        }
+    }
+
+    fn expire_channel(&self, channel: isize, ticks: isize) -> isize {
+        unsafe { ffi::Mix_ExpireChannel(channel as c_int, ticks as c_int) as isize }
+    }
+
+    fn fade_out_channel(&self, channel: isize, ms: isize) -> isize {
+        unsafe { ffi::Mix_FadeOutChannel(channel as c_int, ms as c_int) as isize }
+    }
+
+    fn playing(&self, channel: isize) -> isize {
+        unsafe { ffi::Mix_Playing(channel as c_int) as isize }
+    }
+
+    fn paused(&self, channel: isize) -> isize {
+        unsafe { ffi::Mix_Paused(channel as c_int) as isize }
+    }
+
+    fn fading_channel(&self, channel: isize) -> Fading {
+        Fading::from_raw(unsafe { ffi::Mix_FadingChannel(channel as c_int) as c_uint })
+    }
+
+    fn channel_finished(&self, callback: Option<extern "C" fn(c_int)>) {
+        unsafe { ffi::Mix_ChannelFinished(callback) }
+    }
+
+    fn set_panning(&self, channel: isize, left: u8, right: u8) -> bool {
+        unsafe { ffi::Mix_SetPanning(channel as c_int, left, right) != 0 }
+    }
+
+    fn set_distance(&self, channel: isize, distance: u8) -> bool {
+        unsafe { ffi::Mix_SetDistance(channel as c_int, distance) != 0 }
+    }
+
+    fn set_position(&self, channel: isize, angle: i16, distance: u8) -> bool {
+        unsafe { ffi::Mix_SetPosition(channel as c_int, angle, distance) != 0 }
+    }
+
+    fn set_reverse_stereo(&self, channel: isize, flip: bool) -> bool {
+        unsafe { ffi::Mix_SetReverseStereo(channel as c_int, flip as c_int) != 0 }
+    }
+
+    fn unregister_all_effects(&self, channel: isize) -> bool {
+        unsafe { ffi::Mix_UnregisterAllEffects(channel as c_int) != 0 }
+    }
+
+    unsafe fn register_effect(&self,
+                              channel: isize,
+                              f: Mix_EffectFunc_t,
+                              d: Mix_EffectDone_t,
+                              arg: *mut c_void)
+                              -> bool {
+        ffi::Mix_RegisterEffect(channel as c_int, f, d, arg) != 0
+    }
+
+    fn unregister_effect(&self, channel: isize, f: Mix_EffectFunc_t) -> bool {
+        unsafe { ffi::Mix_UnregisterEffect(channel as c_int, f) != 0 }
+    }
+
+    unsafe fn set_post_mix(&self, f: Option<MixFunc>, arg: *mut c_void) {
+        ffi::Mix_SetPostMix(f, arg)
+    }
+
+    fn reserve_channels(&self, num: isize) -> isize {
+        unsafe { ffi::Mix_ReserveChannels(num as c_int) as isize }
+    }
+
+    fn group_channel(&self, channel: isize, tag: isize) -> bool {
+        unsafe { ffi::Mix_GroupChannel(channel as c_int, tag as c_int) == 1 }
+    }
+
+    fn group_channels(&self, from: isize, to: isize, tag: isize) -> isize {
+        unsafe { ffi::Mix_GroupChannels(from as c_int, to as c_int, tag as c_int) as isize }
+    }
+
+    fn group_count(&self, tag: isize) -> isize {
+        unsafe { ffi::Mix_GroupCount(tag as c_int) as isize }
+    }
+
+    fn group_available(&self, tag: isize) -> isize {
+        unsafe { ffi::Mix_GroupAvailable(tag as c_int) as isize }
+    }
+
+    fn group_oldest(&self, tag: isize) -> isize {
+        unsafe { ffi::Mix_GroupOldest(tag as c_int) as isize }
+    }
+
+    fn group_newer(&self, tag: isize) -> isize {
+        unsafe { ffi::Mix_GroupNewer(tag as c_int) as isize }
+    }
+
+    fn fade_out_group(&self, tag: isize, ms: isize) -> isize {
+        unsafe { ffi::Mix_FadeOutGroup(tag as c_int, ms as c_int) as isize }
+    }
+
+    fn halt_group(&self, tag: isize) {
+        unsafe {
+            ffi::Mix_HaltGroup(tag as c_int);
+        }
+    }
+
+    fn load_mus(&self, file: &CStr) -> MusicHandle {
+        MusicHandle::new(unsafe { ffi::Mix_LoadMUS(file.as_ptr()) })
+    }
+
+    unsafe fn load_mus_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> MusicHandle {
+        MusicHandle::new(ffi::Mix_LoadMUS_RW(src, freesrc as c_int))
+    }
+
+    unsafe fn load_mus_type_rw(&self,
+                               src: *mut SDL_RWops,
+                               music_type: MusicType,
+                               freesrc: bool)
+                               -> MusicHandle {
+        let music = ffi::Mix_LoadMUSType_RW(src,
+                                            music_type as ffi::Mix_MusicType,
+                                            freesrc as c_int);
+        MusicHandle::new(music)
+    }
+
+    unsafe fn free_music(&self, music: MusicHandle) {
+        ffi::Mix_FreeMusic(music.as_ptr() as *mut Mix_Music)
+    }
+
+    unsafe fn get_music_type(&self, music: MusicHandle) -> MusicType {
+        MusicType::from_raw(ffi::Mix_GetMusicType(music.as_ptr() as *mut Mix_Music) as c_uint)
+    }
+
+    unsafe fn play_music(&self, music: MusicHandle, loops: isize) -> isize {
+        ffi::Mix_PlayMusic(music.as_ptr() as *mut Mix_Music, loops as c_int) as isize
+    }
+
+    unsafe fn fade_in_music_pos(&self,
+                                music: MusicHandle,
+                                loops: isize,
+                                ms: isize,
+                                position: f64)
+                                -> isize {
+        ffi::Mix_FadeInMusicPos(music.as_ptr() as *mut Mix_Music,
+                                loops as c_int,
+                                ms as c_int,
+                                position as c_double) as isize
+    }
+
+    fn volume_music(&self, volume: isize) -> isize {
+        unsafe { ffi::Mix_VolumeMusic(volume as c_int) as isize }
+    }
+
+    fn pause_music(&self) {
+        unsafe { ffi::Mix_PauseMusic() }
+    }
+
+    fn resume_music(&self) {
+        unsafe { ffi::Mix_ResumeMusic() }
+    }
+
+    fn rewind_music(&self) {
+        unsafe { ffi::Mix_RewindMusic() }
+    }
+
+    fn set_music_position(&self, position: f64) -> isize {
+        unsafe { ffi::Mix_SetMusicPosition(position as c_double) as isize }
+    }
+
+    fn halt_music(&self) {
+        unsafe {
+            ffi::Mix_HaltMusic();
+        }
+    }
+
+    fn fade_out_music(&self, ms: isize) -> isize {
+        unsafe { ffi::Mix_FadeOutMusic(ms as c_int) as isize }
+    }
+
+    fn playing_music(&self) -> bool {
+        unsafe { ffi::Mix_PlayingMusic() == 1 }
+    }
+
+    fn paused_music(&self) -> bool {
+        unsafe { ffi::Mix_PausedMusic() == 1 }
+    }
+
+    fn fading_music(&self) -> Fading {
+        Fading::from_raw(unsafe { ffi::Mix_FadingMusic() as c_uint })
+    }
+
+    fn hook_music_finished(&self, callback: Option<extern "C" fn()>) {
+        unsafe { ffi::Mix_HookMusicFinished(callback) }
+    }
+
+    unsafe fn hook_music(&self, f: Option<MixFunc>, arg: *mut c_void) {
+        ffi::Mix_HookMusic(f, arg)
+    }
+
+    fn set_music_cmd(&self, command: &CStr) -> isize {
+        unsafe { ffi::Mix_SetMusicCMD(command.as_ptr()) as isize }
+    }
+
+    fn set_synchro_value(&self, value: isize) -> isize {
+        unsafe { ffi::Mix_SetSynchroValue(value as c_int) as isize }
+    }
+
+    fn get_synchro_value(&self) -> isize {
+        unsafe { ffi::Mix_GetSynchroValue() as isize }
+    }
+
+    fn set_sound_fonts(&self, paths: Option<&CStr>) -> bool {
+        let paths = paths.map_or(ptr::null(), |paths| paths.as_ptr());
+        unsafe { ffi::Mix_SetSoundFonts(paths) != 0 }
+    }
+
+    fn get_sound_fonts(&self) -> Option<String> {
+        unsafe { c_string(ffi::Mix_GetSoundFonts()) }
+    }
+
+    unsafe fn each_sound_font(&self, f: SoundFontFunc, data: *mut c_void) -> bool {
+        ffi::Mix_EachSoundFont(Some(f), data) != 0
+    }
+}
+
+// Empty until first used, then `SdlBackend` unless another was set.
+static BACKEND: RwLock<Option<Arc<dyn Backend>>> = RwLock::new(None);
+
+#[cfg(feature = "native")]
+fn default_backend() -> Arc<dyn Backend> {
+    Arc::new(SdlBackend)
+}
+
+#[cfg(not(feature = "native"))]
+fn default_backend() -> Arc<dyn Backend> {
+    panic!("no mixer backend was set, and SDL_mixer isn't linked without the `native` feature")
+}
+
+/// The backend in use.
+///
+/// # Panics
+///
+/// Without the `native` feature, if no backend was set.
+pub fn current() -> Arc<dyn Backend> {
+    if let Some(ref backend) = *BACKEND.read().unwrap_or_else(|e| e.into_inner()) {
+        return backend.clone();
+    }
+    let mut backend = BACKEND.write().unwrap_or_else(|e| e.into_inner());
+    backend.get_or_insert_with(default_backend).clone()
+}
+
+/// Use `backend` from now on, returning the previous one if any was set or
+/// used.
+///
+/// Chunks, music and devices keep using the backend that created them,
+/// while free functions and `Channel`, `Group` and `MusicPlayer` calls use
+/// the current one.
+///
+/// # Safety
+///
+/// No audio device may be open, and no chunk or music may be playing. The
+/// wrappers keep what each channel and the music player are playing for
+/// one backend at a time, and hand its samples to the callbacks.
+pub unsafe fn set(backend: Arc<dyn Backend>) -> Option<Arc<dyn Backend>> {
+    replace(Some(backend))
+}
+
+// As `set`, but can also go back to no backend at all, as before first use.
+pub(crate) unsafe fn replace(backend: Option<Arc<dyn Backend>>) -> Option<Arc<dyn Backend>> {
+    mem::replace(&mut *BACKEND.write().unwrap_or_else(|e| e.into_inner()), backend)
+}
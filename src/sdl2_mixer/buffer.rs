@@ -0,0 +1,490 @@
+//! Views of audio buffers in whatever format the device uses.
+
+use std::slice;
+
+use {AudioFormat, MixerError, Sample};
+
+/// An immutable view of interleaved samples, typed by the device's
+/// `AudioFormat`.
+///
+/// Only host byte order formats can be viewed, which SDL picks unless a
+/// foreign byte order is asked for in `open_audio`.
+#[derive(Debug, Copy, Clone)]
+pub struct AudioBuffer<'a> {
+    samples: Samples<'a>,
+    // Never 0, and divides the number of samples.
+    channels: usize,
+}
+
+/// The samples of an `AudioBuffer`, as the type of its format.
+#[derive(Debug, Copy, Clone)]
+pub enum Samples<'a> {
+    U8(&'a [u8]),
+    S8(&'a [i8]),
+    U16(&'a [u16]),
+    S16(&'a [i16]),
+    S32(&'a [i32]),
+    F32(&'a [f32]),
+}
+
+/// A mutable view of interleaved samples, typed by the device's
+/// `AudioFormat`, so one effect can handle every format.
+///
+/// Only host byte order formats can be viewed, as for `AudioBuffer`.
+#[derive(Debug)]
+pub struct AudioBufferMut<'a> {
+    samples: SamplesMut<'a>,
+    // Never 0, and divides the number of samples.
+    channels: usize,
+}
+
+/// The samples of an `AudioBufferMut`, as the type of its format.
+#[derive(Debug)]
+pub enum SamplesMut<'a> {
+    U8(&'a mut [u8]),
+    S8(&'a mut [i8]),
+    U16(&'a mut [u16]),
+    S16(&'a mut [i16]),
+    S32(&'a mut [i32]),
+    F32(&'a mut [f32]),
+}
+
+// Evaluate `$body` with the slice of whichever `$kind` variant `$samples` is.
+macro_rules! with_samples {
+    ($kind:ident, $samples:expr, $slice:ident => $body:expr) => {
+        match $samples {
+            $kind::U8($slice) => $body,
+            $kind::S8($slice) => $body,
+            $kind::U16($slice) => $body,
+            $kind::S16($slice) => $body,
+            $kind::S32($slice) => $body,
+            $kind::F32($slice) => $body,
+        }
+    }
+}
+
+// The `$kind` variant for `$format`, around the slice `$cast` evaluates to.
+macro_rules! typed {
+    ($kind:ident, $format:expr, $cast:expr) => {
+        match $format {
+            AudioFormat::U8 => $kind::U8($cast),
+            AudioFormat::S8 => $kind::S8($cast),
+            AudioFormat::U16LSB | AudioFormat::U16MSB => $kind::U16($cast),
+            AudioFormat::S16LSB | AudioFormat::S16MSB => $kind::S16($cast),
+            AudioFormat::S32LSB | AudioFormat::S32MSB => $kind::S32($cast),
+            AudioFormat::F32LSB | AudioFormat::F32MSB => $kind::F32($cast),
+        }
+    }
+}
+
+/// Check that `bytes` can be viewed as samples of `format`.
+fn check(bytes: &[u8], format: AudioFormat, channels: usize) -> Result<(), MixerError> {
+    let size = format.bytes_per_sample();
+    if size > 1 && format.is_big_endian() != cfg!(target_endian = "big") {
+        return Err(MixerError::FormatMismatch(format));
+    }
+    if channels == 0 {
+        return Err(MixerError::InvalidData("audio buffer without channels".to_owned()));
+    }
+    if bytes.len() % (size * channels) != 0 {
+        return Err(MixerError::InvalidData(format!("{} bytes is not a whole number of frames",
+                                                   bytes.len())));
+    }
+    // Every sample type is aligned to its size. An empty Vec's dangling
+    // pointer needn't be, so empty views don't use it.
+    if !bytes.is_empty() && bytes.as_ptr() as usize % size != 0 {
+        return Err(MixerError::InvalidData("misaligned audio buffer".to_owned()));
+    }
+    Ok(())
+}
+
+fn sample_format<T: Sample>(_: &[T]) -> AudioFormat {
+    T::audio_format()
+}
+
+impl<'a> AudioBuffer<'a> {
+    /// View `bytes` as interleaved samples of `format` for `channels` channels.
+    pub fn new(bytes: &'a [u8],
+               format: AudioFormat,
+               channels: usize)
+               -> Result<AudioBuffer<'a>, MixerError> {
+        check(bytes, format, channels)?;
+        let len = bytes.len() / format.bytes_per_sample();
+        let ptr = bytes.as_ptr();
+        Ok(AudioBuffer {
+            samples: typed!(Samples,
+                            format,
+                            if len == 0 {
+                                Default::default()
+                            } else {
+                                unsafe { slice::from_raw_parts(ptr as *const _, len) }
+                            }),
+            channels,
+        })
+    }
+
+    /// The samples, as the type of their format.
+    pub fn samples(&self) -> Samples<'a> {
+        self.samples
+    }
+
+    /// The host byte order format of the samples.
+    pub fn format(&self) -> AudioFormat {
+        with_samples!(Samples, self.samples, samples => sample_format(samples))
+    }
+
+    /// Number of interleaved channels.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Number of samples, for all channels.
+    pub fn len(&self) -> usize {
+        with_samples!(Samples, self.samples, samples => samples.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames, each one sample for every channel.
+    pub fn frame_count(&self) -> usize {
+        self.len() / self.channels
+    }
+
+    /// The sample at `index` as a float between -1.0 and 1.0.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn get(&self, index: usize) -> f32 {
+        with_samples!(Samples, self.samples, samples => samples[index].to_f32())
+    }
+
+    /// Iterate over all samples as floats between -1.0 and 1.0.
+    pub fn iter_f32(&self) -> SamplesF32<'a> {
+        SamplesF32 {
+            buf: *self,
+            pos: 0,
+        }
+    }
+
+    /// Iterate over the frames, each a view of one sample for every channel.
+    pub fn frames(&self) -> Frames<'a> {
+        Frames { rest: *self }
+    }
+
+    fn split_at(self, mid: usize) -> (AudioBuffer<'a>, AudioBuffer<'a>) {
+        macro_rules! split {
+            ($($variant:ident),*) => {
+                match self.samples {
+                    $(Samples::$variant(samples) => {
+                        let (head, tail) = samples.split_at(mid);
+                        (Samples::$variant(head), Samples::$variant(tail))
+                    })*
+                }
+            }
+        }
+        let (head, tail) = split!(U8, S8, U16, S16, S32, F32);
+        (AudioBuffer { samples: head, channels: self.channels },
+         AudioBuffer { samples: tail, channels: self.channels })
+    }
+}
+
+impl<'a> AudioBufferMut<'a> {
+    /// View `bytes` as interleaved samples of `format` for `channels` channels.
+    pub fn new(bytes: &'a mut [u8],
+               format: AudioFormat,
+               channels: usize)
+               -> Result<AudioBufferMut<'a>, MixerError> {
+        check(bytes, format, channels)?;
+        let len = bytes.len() / format.bytes_per_sample();
+        let ptr = bytes.as_mut_ptr();
+        Ok(AudioBufferMut {
+            samples: typed!(SamplesMut,
+                            format,
+                            if len == 0 {
+                                Default::default()
+                            } else {
+                                unsafe { slice::from_raw_parts_mut(ptr as *mut _, len) }
+                            }),
+            channels,
+        })
+    }
+
+    /// An immutable view of the same samples.
+    pub fn as_buffer(&self) -> AudioBuffer<'_> {
+        let samples = match self.samples {
+            SamplesMut::U8(ref samples) => Samples::U8(samples),
+            SamplesMut::S8(ref samples) => Samples::S8(samples),
+            SamplesMut::U16(ref samples) => Samples::U16(samples),
+            SamplesMut::S16(ref samples) => Samples::S16(samples),
+            SamplesMut::S32(ref samples) => Samples::S32(samples),
+            SamplesMut::F32(ref samples) => Samples::F32(samples),
+        };
+        AudioBuffer {
+            samples,
+            channels: self.channels,
+        }
+    }
+
+    /// The samples, as the type of their format.
+    pub fn samples(&self) -> Samples<'_> {
+        self.as_buffer().samples()
+    }
+
+    /// The samples, as the type of their format, to change them in place.
+    pub fn samples_mut(&mut self) -> SamplesMut<'_> {
+        self.reborrow().samples
+    }
+
+    /// The host byte order format of the samples.
+    pub fn format(&self) -> AudioFormat {
+        self.as_buffer().format()
+    }
+
+    /// Number of interleaved channels.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Number of samples, for all channels.
+    pub fn len(&self) -> usize {
+        self.as_buffer().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames, each one sample for every channel.
+    pub fn frame_count(&self) -> usize {
+        self.as_buffer().frame_count()
+    }
+
+    /// The sample at `index` as a float between -1.0 and 1.0.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn get(&self, index: usize) -> f32 {
+        self.as_buffer().get(index)
+    }
+
+    /// Set the sample at `index` from a float between -1.0 and 1.0.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds.
+    pub fn set(&mut self, index: usize, value: f32) {
+        with_samples!(SamplesMut, &mut self.samples, samples => {
+            samples[index] = Sample::from_f32(value)
+        })
+    }
+
+    /// Iterate over all samples as floats between -1.0 and 1.0.
+    pub fn iter_f32(&self) -> SamplesF32<'_> {
+        self.as_buffer().iter_f32()
+    }
+
+    /// Replace every sample by `f` of it, as floats between -1.0 and 1.0.
+    pub fn map_f32<F: FnMut(f32) -> f32>(&mut self, mut f: F) {
+        with_samples!(SamplesMut, &mut self.samples, samples => {
+            for sample in samples.iter_mut() {
+                *sample = Sample::from_f32(f(sample.to_f32()));
+            }
+        })
+    }
+
+    /// Set every sample to silence.
+    pub fn silence(&mut self) {
+        with_samples!(SamplesMut, &mut self.samples, samples => {
+            for sample in samples.iter_mut() {
+                *sample = Sample::silence();
+            }
+        })
+    }
+
+    /// Iterate over the frames, each a view of one sample for every channel.
+    pub fn frames_mut(&mut self) -> FramesMut<'_> {
+        FramesMut { rest: Some(self.reborrow()) }
+    }
+
+    fn reborrow(&mut self) -> AudioBufferMut<'_> {
+        let samples = match self.samples {
+            SamplesMut::U8(ref mut samples) => SamplesMut::U8(samples),
+            SamplesMut::S8(ref mut samples) => SamplesMut::S8(samples),
+            SamplesMut::U16(ref mut samples) => SamplesMut::U16(samples),
+            SamplesMut::S16(ref mut samples) => SamplesMut::S16(samples),
+            SamplesMut::S32(ref mut samples) => SamplesMut::S32(samples),
+            SamplesMut::F32(ref mut samples) => SamplesMut::F32(samples),
+        };
+        AudioBufferMut {
+            samples,
+            channels: self.channels,
+        }
+    }
+
+    fn split_at(self, mid: usize) -> (AudioBufferMut<'a>, AudioBufferMut<'a>) {
+        macro_rules! split {
+            ($($variant:ident),*) => {
+                match self.samples {
+                    $(SamplesMut::$variant(samples) => {
+                        let (head, tail) = samples.split_at_mut(mid);
+                        (SamplesMut::$variant(head), SamplesMut::$variant(tail))
+                    })*
+                }
+            }
+        }
+        let channels = self.channels;
+        let (head, tail) = split!(U8, S8, U16, S16, S32, F32);
+        (AudioBufferMut { samples: head, channels },
+         AudioBufferMut { samples: tail, channels })
+    }
+}
+
+/// Iterator over samples as floats, see `AudioBuffer::iter_f32`.
+pub struct SamplesF32<'a> {
+    buf: AudioBuffer<'a>,
+    pos: usize,
+}
+
+impl<'a> Iterator for SamplesF32<'a> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos < self.buf.len() {
+            self.pos += 1;
+            Some(self.buf.get(self.pos - 1))
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.buf.len() - self.pos;
+        (left, Some(left))
+    }
+}
+
+/// Iterator over the frames of an `AudioBuffer`, see `frames`.
+pub struct Frames<'a> {
+    rest: AudioBuffer<'a>,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = AudioBuffer<'a>;
+
+    fn next(&mut self) -> Option<AudioBuffer<'a>> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (frame, rest) = self.rest.split_at(self.rest.channels());
+        self.rest = rest;
+        Some(frame)
+    }
+}
+
+/// Iterator over the frames of an `AudioBufferMut`, see `frames_mut`.
+pub struct FramesMut<'a> {
+    rest: Option<AudioBufferMut<'a>>,
+}
+
+impl<'a> Iterator for FramesMut<'a> {
+    type Item = AudioBufferMut<'a>;
+
+    fn next(&mut self) -> Option<AudioBufferMut<'a>> {
+        let rest = self.rest.take()?;
+        if rest.is_empty() {
+            return None;
+        }
+        let channels = rest.channels();
+        let (frame, rest) = rest.split_at(channels);
+        self.rest = Some(rest);
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {AudioFormat, MixerError};
+    use super::{AudioBuffer, AudioBufferMut, Samples, SamplesMut};
+
+    // Two stereo frames of 16-bit samples.
+    fn s16_bytes() -> Vec<u8> {
+        [0i16, 16384, -16384, 32767].iter().flat_map(|s| s.to_ne_bytes().to_vec()).collect()
+    }
+
+    fn s16_host() -> AudioFormat {
+        if cfg!(target_endian = "big") { AudioFormat::S16MSB } else { AudioFormat::S16LSB }
+    }
+
+    #[test]
+    fn views_samples_of_the_format() {
+        let bytes = s16_bytes();
+        let buf = AudioBuffer::new(&bytes, s16_host(), 2).unwrap();
+        assert_eq!(buf.format(), s16_host());
+        assert_eq!((buf.channels(), buf.len(), buf.frame_count()), (2, 4, 2));
+        match buf.samples() {
+            Samples::S16(samples) => assert_eq!(samples, &[0, 16384, -16384, 32767]),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(buf.iter_f32().collect::<Vec<_>>(), vec![0.0, 0.5, -0.5, 32767.0 / 32768.0]);
+        let frames: Vec<_> = buf.frames().map(|frame| (frame.len(), frame.get(0))).collect();
+        assert_eq!(frames, vec![(2, 0.0), (2, -0.5)]);
+    }
+
+    #[test]
+    fn changes_samples_in_place() {
+        let mut bytes = s16_bytes();
+        {
+            let mut buf = AudioBufferMut::new(&mut bytes, s16_host(), 2).unwrap();
+            buf.set(0, 0.25);
+            for mut frame in buf.frames_mut() {
+                frame.set(1, 0.0);
+            }
+            if let SamplesMut::S16(samples) = buf.samples_mut() {
+                samples[2] = 1;
+            }
+            assert_eq!(buf.iter_f32().collect::<Vec<_>>(), vec![0.25, 0.0, 1.0 / 32768.0, 0.0]);
+            buf.map_f32(|sample| -sample);
+            assert_eq!(buf.get(0), -0.25);
+            buf.silence();
+            assert!(buf.iter_f32().all(|sample| sample == 0.0));
+        }
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rejects_buffers_that_are_no_whole_frames() {
+        let bytes = s16_bytes();
+        for &(len, channels) in &[(8, 0), (8, 3), (7, 1), (0, 0)] {
+            match AudioBuffer::new(&bytes[..len], s16_host(), channels) {
+                Err(MixerError::InvalidData(_)) => {}
+                other => panic!("{} bytes, {} channels: {:?}", len, channels, other),
+            }
+        }
+        let mut bytes = bytes;
+        assert!(AudioBufferMut::new(&mut bytes, s16_host(), 0).is_err());
+        assert!(AudioBuffer::new(&[], AudioFormat::U8, 2).unwrap().frames().next().is_none());
+    }
+
+    #[test]
+    fn rejects_misaligned_and_foreign_buffers() {
+        let bytes = [0u8; 10];
+        let misaligned = if bytes.as_ptr() as usize % 2 == 0 { &bytes[1..9] } else { &bytes[..8] };
+        assert!(AudioBuffer::new(misaligned, s16_host(), 1).is_err());
+        let foreign = if cfg!(target_endian = "big") {
+            AudioFormat::S16LSB
+        } else {
+            AudioFormat::S16MSB
+        };
+        match AudioBuffer::new(&s16_bytes(), foreign, 2) {
+            Err(MixerError::FormatMismatch(format)) => assert_eq!(format, foreign),
+            other => panic!("{:?}", other),
+        }
+        let empty = &bytes[1..1];
+        assert_eq!(AudioBuffer::new(empty, s16_host(), 2).unwrap().len(), 0);
+        assert_eq!(AudioBufferMut::new(&mut [], AudioFormat::F32LSB, 1).unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,285 @@
+//! Mixing on SDL's headless audio drivers, one buffer at a time.
+
+use std::env;
+use std::ffi::{CStr, OsString};
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use {ffi, install_post_mix, lock_ignore_poison, open_audio, query_spec, AudioDevice, AudioLock,
+     AudioSpec, MixerError, PostMixFn, PostMixHook};
+
+#[cfg(windows)]
+const NULL_FILE: &str = "NUL";
+#[cfg(not(windows))]
+const NULL_FILE: &str = "/dev/null";
+
+/// Sets environment variables, restoring them when dropped.
+///
+/// The environment belongs to the whole process, so this is only used while
+/// `Headless::open` initializes SDL audio.
+struct EnvGuard {
+    saved: Vec<(&'static str, Option<OsString>)>,
+}
+
+impl EnvGuard {
+    fn set(vars: &[(&'static str, &str)]) -> EnvGuard {
+        let saved = vars.iter().map(|&(name, _)| (name, env::var_os(name))).collect();
+        for &(name, value) in vars {
+            env::set_var(name, value);
+        }
+        EnvGuard { saved }
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for &(name, ref value) in &self.saved {
+            match *value {
+                Some(ref value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+/// The SDL audio subsystem, initialized on a given driver and quit when dropped.
+struct AudioDriver;
+
+impl AudioDriver {
+    /// Initialize SDL audio, which must pick the driver called `name` as
+    /// `SDL_AUDIODRIVER` says.
+    ///
+    /// Fails if SDL audio is already initialized, rather than taking it over.
+    fn init(name: &str) -> Result<AudioDriver, MixerError> {
+        unsafe {
+            if ffi::SDL_WasInit(ffi::SDL_INIT_AUDIO) != 0 {
+                return Err(MixerError::Sdl("SDL audio is already initialized".to_owned()));
+            }
+            if ffi::SDL_InitSubSystem(ffi::SDL_INIT_AUDIO) != 0 {
+                return Err(MixerError::last());
+            }
+            let driver = AudioDriver;
+            let current = ffi::SDL_GetCurrentAudioDriver();
+            if current.is_null() || CStr::from_ptr(current).to_bytes() != name.as_bytes() {
+                return Err(MixerError::Sdl(format!("The {} audio driver is not in use", name)));
+            }
+            Ok(driver)
+        }
+    }
+}
+
+impl Drop for AudioDriver {
+    fn drop(&mut self) {
+        unsafe { ffi::SDL_QuitSubSystem(ffi::SDL_INIT_AUDIO) };
+    }
+}
+
+// How long `Headless::advance_frames` waits for the audio thread.
+const MIX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Frames mixed, and up to which the audio thread may mix before pausing.
+struct Clock {
+    mixed: u64,
+    target: u64,
+}
+
+struct Shared {
+    clock: Mutex<Clock>,
+    mixed: Condvar,
+    output: Mutex<Vec<u8>>,
+}
+
+/// The mixer opened on a headless driver, which only mixes when told to.
+///
+/// The audio thread pauses the device once it mixed enough, so mixer
+/// functions are called from the owning thread between two buffers.
+pub struct Headless {
+    hook: Option<PostMixHook>,
+    device: Option<AudioDevice>,
+    spec: AudioSpec,
+    shared: Arc<Shared>,
+    _driver: AudioDriver,
+}
+
+impl Headless {
+    /// Open the mixer on the driver called `driver`, mixing `chunk_size`
+    /// frames at a time.
+    ///
+    /// The headless mixer must own SDL audio: it fails if SDL audio is
+    /// already initialized, and quits it when dropped. SDL only reads the
+    /// driver and its settings from the environment, which is changed until
+    /// the device is open, so no other thread may use the environment then.
+    pub fn open(driver: &str, spec: AudioSpec, chunk_size: isize) -> Result<Headless, MixerError> {
+        let (audio_driver, device) = {
+            // The disk driver writes to SDL_DISKAUDIOFILE once the device is
+            // opened, pausing between buffers for SDL_DISKAUDIODELAY ms.
+            let _env = EnvGuard::set(&[("SDL_AUDIODRIVER", driver),
+                                       ("SDL_DISKAUDIOFILE", NULL_FILE),
+                                       ("SDL_DISKAUDIODELAY", "0")]);
+            let audio_driver = AudioDriver::init(driver)?;
+            let device = open_audio(spec.frequency, spec.format, spec.channels, chunk_size)?;
+            (audio_driver, device)
+        };
+        let spec = query_spec()?;
+        let shared = Arc::new(Shared {
+            clock: Mutex::new(Clock { mixed: 0, target: 0 }),
+            mixed: Condvar::new(),
+            output: Mutex::new(Vec::new()),
+        });
+        let hook = {
+            let shared = shared.clone();
+            let frame_size = spec.bytes_per_frame();
+            let hook: PostMixFn = Box::new(move |stream: &mut [u8]| {
+                let mut clock = lock_ignore_poison(&shared.clock);
+                if clock.mixed < clock.target {
+                    lock_ignore_poison(&shared.output).extend_from_slice(stream);
+                    clock.mixed += (stream.len() / frame_size) as u64;
+                }
+                // Otherwise the audio thread got past the pause below before
+                // it took effect, right after opening.
+                if clock.mixed >= clock.target {
+                    // Called with the device locked, which is recursive.
+                    unsafe { ffi::SDL_PauseAudio(1) };
+                }
+                shared.mixed.notify_all();
+            });
+            // Nothing is mixed until the first `advance_frames`.
+            let _audio = AudioLock::new();
+            unsafe { ffi::SDL_PauseAudio(1) };
+            install_post_mix(hook)
+        };
+        Ok(Headless {
+            hook: Some(hook),
+            device: Some(device),
+            spec,
+            shared,
+            _driver: audio_driver,
+        })
+    }
+
+    pub fn device(&self) -> &AudioDevice {
+        self.device.as_ref().unwrap()
+    }
+
+    /// The format the device mixes in.
+    pub fn spec(&self) -> AudioSpec {
+        self.spec
+    }
+
+    /// The number of frames mixed so far.
+    #[cfg(feature = "testing")]
+    pub fn frames_mixed(&self) -> u64 {
+        lock_ignore_poison(&self.shared.clock).mixed
+    }
+
+    /// Mix at least `frames` more frames, rounded up to whole buffers, and
+    /// wait until the device is paused again.
+    pub fn advance_frames(&self, frames: u64) -> Result<(), MixerError> {
+        let target = {
+            let mut clock = lock_ignore_poison(&self.shared.clock);
+            clock.target = clock.mixed + frames;
+            clock.target
+        };
+        if frames == 0 {
+            return Ok(());
+        }
+        unsafe { ffi::SDL_PauseAudio(0) };
+        let mut clock = lock_ignore_poison(&self.shared.clock);
+        while clock.mixed < target {
+            let (next, timeout) = self.shared
+                                      .mixed
+                                      .wait_timeout(clock, MIX_TIMEOUT)
+                                      .unwrap_or_else(|e| e.into_inner());
+            clock = next;
+            if timeout.timed_out() && clock.mixed < target {
+                return Err(MixerError::Sdl(format!("No audio was mixed for {:?}", MIX_TIMEOUT)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The mixed output since the last call, in the format of the device.
+    pub fn take_output(&self) -> Vec<u8> {
+        mem::take(&mut *lock_ignore_poison(&self.shared.output))
+    }
+}
+
+impl Drop for Headless {
+    fn drop(&mut self) {
+        drop(self.hook.take());
+        if let Some(ref device) = self.device {
+            device.all_channels().halt();
+            device.music().halt();
+        }
+        drop(self.device.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::{AudioDriver, EnvGuard, Headless};
+    use {ffi, lock_ignore_poison, AudioFormat, AudioSpec, TEST_LOCK};
+
+    fn audio_initialized() -> bool {
+        unsafe { ffi::SDL_WasInit(ffi::SDL_INIT_AUDIO) != 0 }
+    }
+
+    #[test]
+    fn env_guard_restores_the_environment() {
+        let _lock = lock_ignore_poison(&TEST_LOCK);
+        env::set_var("SDL2_MIXER_TEST_SET", "before");
+        env::remove_var("SDL2_MIXER_TEST_UNSET");
+        {
+            let _env = EnvGuard::set(&[("SDL2_MIXER_TEST_SET", "during"),
+                                       ("SDL2_MIXER_TEST_UNSET", "during")]);
+            assert_eq!(env::var("SDL2_MIXER_TEST_SET").unwrap(), "during");
+            assert_eq!(env::var("SDL2_MIXER_TEST_UNSET").unwrap(), "during");
+        }
+        assert_eq!(env::var("SDL2_MIXER_TEST_SET").unwrap(), "before");
+        assert!(env::var_os("SDL2_MIXER_TEST_UNSET").is_none());
+        env::remove_var("SDL2_MIXER_TEST_SET");
+    }
+
+    #[test]
+    fn audio_driver_owns_sdl_audio() {
+        let _lock = lock_ignore_poison(&TEST_LOCK);
+        let _env = EnvGuard::set(&[("SDL_AUDIODRIVER", "dummy")]);
+        {
+            let _driver = AudioDriver::init("dummy").unwrap();
+            assert!(audio_initialized());
+            assert!(AudioDriver::init("dummy").is_err());
+            assert!(audio_initialized());
+        }
+        assert!(!audio_initialized());
+    }
+
+    #[test]
+    fn audio_driver_fails_on_another_driver() {
+        let _lock = lock_ignore_poison(&TEST_LOCK);
+        let _env = EnvGuard::set(&[("SDL_AUDIODRIVER", "dummy")]);
+        assert!(AudioDriver::init("disk").is_err());
+        assert!(!audio_initialized());
+    }
+
+    #[test]
+    fn headless_mixes_whole_buffers_when_told_to() {
+        let _lock = lock_ignore_poison(&TEST_LOCK);
+        let spec = AudioSpec {
+            frequency: 22050,
+            format: AudioFormat::S16LSB,
+            channels: 2,
+        };
+        let headless = Headless::open("disk", spec, 256).unwrap();
+        assert!(env::var_os("SDL_DISKAUDIOFILE").is_none());
+        assert!(Headless::open("disk", spec, 256).is_err());
+        assert!(headless.take_output().is_empty());
+        headless.advance_frames(1).unwrap();
+        assert_eq!(headless.take_output().len(), 256 * 4);
+        headless.advance_frames(257).unwrap();
+        assert_eq!(headless.take_output().len(), 512 * 4);
+        drop(headless);
+        assert!(!audio_initialized());
+    }
+}
@@ -0,0 +1,294 @@
+//! Editing operations producing new chunks from existing ones.
+//!
+//! Samples are in the format of the opened audio device, which every chunk
+//! is converted to when loaded.
+
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use {query_spec, AudioFormat, AudioSpec, Chunk, MixerError, Sample};
+
+fn decode_as<T: Sample>(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(mem::size_of::<T>())
+         .map(|sample| unsafe { ptr::read_unaligned(sample.as_ptr() as *const T) }.to_f32())
+         .collect()
+}
+
+fn encode_as<T: Sample>(samples: &[f32]) -> Vec<u8> {
+    let size = mem::size_of::<T>();
+    let mut bytes = Vec::with_capacity(samples.len() * size);
+    for &value in samples {
+        let sample = T::from_f32(value);
+        bytes.extend_from_slice(unsafe {
+            slice::from_raw_parts(&sample as *const T as *const u8, size)
+        });
+    }
+    bytes
+}
+
+/// The device spec, if its samples can be edited.
+fn editable_spec() -> Result<AudioSpec, MixerError> {
+    let spec = query_spec()?;
+    let format = spec.format;
+    if format.bytes_per_sample() > 1 && format.is_big_endian() != cfg!(target_endian = "big") {
+        return Err(MixerError::FormatMismatch(format));
+    }
+    Ok(spec)
+}
+
+/// The samples of `chunk`, if they are whole frames of `spec`.
+///
+/// A chunk loaded before the device was reopened in another format may
+/// not be, though a change of format isn't always noticed.
+fn frames_of<'a>(chunk: &'a Chunk, spec: &AudioSpec) -> Result<&'a [u8], MixerError> {
+    let bytes = chunk.bytes();
+    if bytes.len() % spec.bytes_per_frame() != 0 {
+        return Err(MixerError::InvalidData("chunk is not in the format of the audio device"
+            .to_owned()));
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn decode(bytes: &[u8], format: AudioFormat) -> Vec<f32> {
+    match format {
+        AudioFormat::U8 => decode_as::<u8>(bytes),
+        AudioFormat::S8 => decode_as::<i8>(bytes),
+        AudioFormat::U16LSB | AudioFormat::U16MSB => decode_as::<u16>(bytes),
+        AudioFormat::S16LSB | AudioFormat::S16MSB => decode_as::<i16>(bytes),
+        AudioFormat::S32LSB | AudioFormat::S32MSB => decode_as::<i32>(bytes),
+        AudioFormat::F32LSB | AudioFormat::F32MSB => decode_as::<f32>(bytes),
+    }
+}
+
+pub(crate) fn encode(samples: &[f32], format: AudioFormat) -> Vec<u8> {
+    match format {
+        AudioFormat::U8 => encode_as::<u8>(samples),
+        AudioFormat::S8 => encode_as::<i8>(samples),
+        AudioFormat::U16LSB | AudioFormat::U16MSB => encode_as::<u16>(samples),
+        AudioFormat::S16LSB | AudioFormat::S16MSB => encode_as::<i16>(samples),
+        AudioFormat::S32LSB | AudioFormat::S32MSB => encode_as::<i32>(samples),
+        AudioFormat::F32LSB | AudioFormat::F32MSB => encode_as::<f32>(samples),
+    }
+}
+
+impl Chunk {
+    /// A new chunk of `bytes`, with the same volume as this one.
+    fn derive(&self, bytes: Vec<u8>) -> Result<Chunk, MixerError> {
+        let mut chunk = Chunk::from_raw_samples(bytes)?;
+        chunk.set_volume(self.get_volume());
+        Ok(chunk)
+    }
+
+    /// A new chunk of the samples changed by `f`, as floats between -1.0 and 1.0.
+    fn map_samples<F>(&self, f: F) -> Result<Chunk, MixerError>
+        where F: FnOnce(&mut [f32], &AudioSpec)
+    {
+        let spec = editable_spec()?;
+        let mut samples = decode(frames_of(self, &spec)?, spec.format);
+        f(&mut samples, &spec);
+        self.derive(encode(&samples, spec.format))
+    }
+
+    /// A new chunk of the part of this one between `start` and `end`, both
+    /// clamped to its length.
+    pub fn slice(&self, start: Duration, end: Duration) -> Result<Chunk, MixerError> {
+        let spec = editable_spec()?;
+        let frame_size = spec.bytes_per_frame();
+        let bytes = frames_of(self, &spec)?;
+        let frames = bytes.len() / frame_size;
+        let start = (spec.frames_in(start) as usize).min(frames);
+        let end = (spec.frames_in(end) as usize).min(frames).max(start);
+        self.derive(bytes[start * frame_size..end * frame_size].to_vec())
+    }
+
+    /// A new chunk playing `chunks` one after another.
+    pub fn concat(chunks: &[&Chunk]) -> Result<Chunk, MixerError> {
+        let spec = editable_spec()?;
+        let mut bytes = Vec::with_capacity(chunks.iter().map(|chunk| chunk.len_bytes()).sum());
+        for chunk in chunks {
+            bytes.extend_from_slice(frames_of(chunk, &spec)?);
+        }
+        Chunk::from_raw_samples(bytes)
+    }
+
+    /// A new chunk playing this one backwards.
+    pub fn reversed(&self) -> Result<Chunk, MixerError> {
+        let spec = editable_spec()?;
+        let mut bytes = Vec::with_capacity(self.len_bytes());
+        for frame in frames_of(self, &spec)?.chunks_exact(spec.bytes_per_frame()).rev() {
+            bytes.extend_from_slice(frame);
+        }
+        self.derive(bytes)
+    }
+
+    /// A new chunk scaled so its loudest sample reaches `peak`, between 0.0
+    /// and 1.0. A silent chunk stays silent.
+    pub fn normalized(&self, peak: f32) -> Result<Chunk, MixerError> {
+        self.map_samples(|samples, _| {
+            let max = samples.iter().fold(0.0f32, |max, sample| max.max(sample.abs()));
+            if max > 0.0 {
+                let gain = peak / max;
+                for sample in samples.iter_mut() {
+                    *sample *= gain;
+                }
+            }
+        })
+    }
+
+    /// A new chunk with every sample multiplied by `gain`, clipping integer
+    /// samples that get too loud.
+    pub fn with_gain(&self, gain: f32) -> Result<Chunk, MixerError> {
+        self.map_samples(|samples, _| {
+            for sample in samples.iter_mut() {
+                *sample *= gain;
+            }
+        })
+    }
+
+    /// A new chunk fading in linearly from silence over `fade_in`, and out
+    /// to silence over `fade_out`.
+    pub fn with_fade(&self, fade_in: Duration, fade_out: Duration) -> Result<Chunk, MixerError> {
+        self.map_samples(|samples, spec| {
+            let channels = spec.channels as usize;
+            let frames = samples.len() / channels;
+            let fade_in = (spec.frames_in(fade_in) as usize).min(frames);
+            let fade_out = (spec.frames_in(fade_out) as usize).min(frames);
+            for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+                let mut gain = 1.0;
+                if i < fade_in {
+                    gain *= i as f32 / fade_in as f32;
+                }
+                if frames - i <= fade_out {
+                    gain *= (frames - i - 1) as f32 / fade_out as f32;
+                }
+                for sample in frame {
+                    *sample *= gain;
+                }
+            }
+        })
+    }
+
+    /// A new chunk without the leading and trailing frames where every
+    /// sample is quieter than `threshold`, between 0.0 and 1.0.
+    pub fn trimmed(&self, threshold: f32) -> Result<Chunk, MixerError> {
+        let spec = editable_spec()?;
+        let channels = spec.channels as usize;
+        let frame_size = spec.bytes_per_frame();
+        let bytes = frames_of(self, &spec)?;
+        let samples = decode(bytes, spec.format);
+        let loud = |frame: &[f32]| frame.iter().any(|sample| sample.abs() >= threshold);
+        let frames: Vec<_> = samples.chunks_exact(channels).collect();
+        let start = frames.iter().position(|frame| loud(frame)).unwrap_or(frames.len());
+        let end = frames.iter().rposition(|frame| loud(frame)).map_or(start, |end| end + 1);
+        self.derive(bytes[start * frame_size..end * frame_size].to_vec())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::time::Duration;
+    use fake::FakeMixer;
+    use {AudioSpec, Chunk, MixerError, Samples, AUDIO_S16SYS};
+
+    fn open(channels: isize) -> FakeMixer {
+        FakeMixer::open_spec(AudioSpec {
+                                 frequency: 1000,
+                                 format: AUDIO_S16SYS,
+                                 channels,
+                             })
+            .unwrap()
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn chunk(samples: &[i16]) -> Chunk {
+        Chunk::from_raw_samples(samples.iter().flat_map(|s| s.to_ne_bytes().to_vec()).collect())
+            .unwrap()
+    }
+
+    fn samples(chunk: &Chunk) -> Vec<i16> {
+        match chunk.samples().unwrap().samples() {
+            Samples::S16(samples) => samples.to_vec(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_chunks() {
+        let _mixer = open(2);
+        let empty = chunk(&[]);
+        assert!(samples(&empty.slice(ms(0), ms(10)).unwrap()).is_empty());
+        assert!(samples(&Chunk::concat(&[]).unwrap()).is_empty());
+        assert!(samples(&Chunk::concat(&[&empty, &empty]).unwrap()).is_empty());
+        assert!(samples(&empty.reversed().unwrap()).is_empty());
+        assert!(samples(&empty.normalized(1.0).unwrap()).is_empty());
+        assert!(samples(&empty.with_gain(2.0).unwrap()).is_empty());
+        assert!(samples(&empty.with_fade(ms(10), ms(10)).unwrap()).is_empty());
+        assert!(samples(&empty.trimmed(0.1).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn slice_reverse_and_trim_whole_frames() {
+        let _mixer = open(2);
+        let chunk = chunk(&[0, 0, 1, -1, 2, -2, 0, 300, 0, 0]);
+        assert_eq!(samples(&chunk.slice(ms(1), ms(3)).unwrap()), vec![1, -1, 2, -2]);
+        assert_eq!(samples(&chunk.slice(ms(4), ms(100)).unwrap()), vec![0, 0]);
+        assert!(samples(&chunk.slice(ms(3), ms(1)).unwrap()).is_empty());
+        assert_eq!(samples(&chunk.reversed().unwrap()),
+                   vec![0, 0, 0, 300, 2, -2, 1, -1, 0, 0]);
+        assert_eq!(samples(&chunk.trimmed(100.0 / 32768.0).unwrap()), vec![0, 300]);
+    }
+
+    #[test]
+    fn fade_longer_than_the_chunk() {
+        let _mixer = open(1);
+        let chunk = chunk(&[16384; 4]);
+        assert_eq!(samples(&chunk.with_fade(ms(10), ms(0)).unwrap()),
+                   vec![0, 4096, 8192, 12288]);
+        assert_eq!(samples(&chunk.with_fade(ms(0), ms(10)).unwrap()),
+                   vec![12288, 8192, 4096, 0]);
+        assert_eq!(samples(&chunk.with_fade(ms(10), ms(10)).unwrap()), vec![0, 2048, 2048, 0]);
+    }
+
+    #[test]
+    fn concat_with_mismatched_formats() {
+        let stereo = {
+            let _mixer = open(2);
+            chunk(&[1, 2, 3, 4])
+        };
+        let _mixer = open(3);
+        let mono = chunk(&[5, 6, 7]);
+        match Chunk::concat(&[&mono, &stereo]) {
+            Err(MixerError::InvalidData(_)) => {}
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("chunks of different formats were concatenated"),
+        }
+        assert!(stereo.reversed().is_err());
+        assert!(stereo.with_gain(0.5).is_err());
+    }
+
+    #[test]
+    fn normalize_silence() {
+        let _mixer = open(1);
+        let silence = chunk(&[0; 8]);
+        assert_eq!(samples(&silence.normalized(1.0).unwrap()), vec![0; 8]);
+        let quiet = chunk(&[0, 4096, -8192, 0]);
+        assert_eq!(samples(&quiet.normalized(0.5).unwrap()), vec![0, 8192, -16384, 0]);
+    }
+
+    #[test]
+    fn gain_clips_integer_samples() {
+        let _mixer = open(1);
+        let mut loud = chunk(&[16384, -16384, 32767, -32768, 100]);
+        loud.set_volume(64);
+        let louder = loud.with_gain(4.0).unwrap();
+        assert_eq!(samples(&louder), vec![32767, -32768, 32767, -32768, 400]);
+        assert_eq!(louder.get_volume(), 64);
+        assert_eq!(samples(&loud.with_gain(-1.0).unwrap()),
+                   vec![-16384, 16384, -32767, 32767, -100]);
+    }
+}
@@ -0,0 +1,1525 @@
+//! A fake mixer for unit tests, enabled by the `testing` feature.
+//!
+//! `FakeMixer` installs a backend that keeps the state of channels, groups
+//! and music in Rust and follows a virtual clock. Nothing is decoded:
+//! chunks play for as long as their samples last, and music for the length
+//! it was made with, so tests run instantly and the same every time.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use sdl2_mixer::fake::FakeMixer;
+//!
+//! let mixer = FakeMixer::open().unwrap();
+//! let beep = mixer.chunk(Duration::from_millis(100)).unwrap();
+//! let channel = mixer.device().all_channels().play(&beep, 0).unwrap();
+//! mixer.advance(Duration::from_millis(99));
+//! assert!(channel.is_playing());
+//! mixer.advance(Duration::from_millis(1));
+//! assert!(!channel.is_playing());
+//! ```
+//!
+//! No SDL or SDL_mixer function is called while the fake is installed,
+//! except by `EventSink::Sdl`, so tests need no audio device. Both libraries
+//! are still linked unless the default `native` feature is turned off.
+//! Samples are made from raw samples or in-memory WAV files, not decoded
+//! from files.
+//!
+//! Channels play silence: the music hook fills the mixed stream block by
+//! block as the clock moves, custom effects are called with a silent buffer
+//! for each block their channel plays, and post-mix callbacks see what the
+//! music hook played.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+use libc::{c_int, c_void};
+use Version;
+
+use backend::{self, Backend, MixFunc, Mix_Chunk, Mix_EffectDone_t, Mix_EffectFunc_t, MusicHandle,
+              SDL_RWops, SoundFontFunc};
+use edit::{decode, encode};
+use {lock_ignore_poison, open_audio, query_spec, AudioDevice, AudioFormat, AudioSpec, Channel,
+     Chunk, Fading, MixerError, Music, MusicType, DEFAULT_CHANNELS, DEFAULT_FORMAT,
+     DEFAULT_FREQUENCY, MAX_VOLUME, OPEN_DEVICES, TEST_LOCK};
+
+// Channels allocated when the device is opened, as in SDL_mixer.
+const CHANNELS: usize = 8;
+
+/// The built-in effects set on a channel, see `FakeMixer::effects`.
+///
+/// Panning, distance and position share one effect in SDL_mixer, so
+/// `set_position` also sets the distance.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelEffects {
+    /// Left and right volume, 255 when not panned.
+    pub panning: (u8, u8),
+    /// 0 is closest.
+    pub distance: u8,
+    /// Direction in degrees, between 0 and 359.
+    pub angle: i16,
+    pub reverse_stereo: bool,
+}
+
+impl Default for ChannelEffects {
+    fn default() -> ChannelEffects {
+        ChannelEffects {
+            panning: (255, 255),
+            distance: 0,
+            angle: 0,
+            reverse_stereo: false,
+        }
+    }
+}
+
+/// The mixer faked for unit tests, see the module documentation.
+///
+/// Channel fades and timed playback follow the virtual clock like the
+/// samples do, so `advance` returns at once.
+pub struct FakeMixer {
+    backend: Arc<FakeBackend>,
+    device: Option<AudioDevice>,
+    spec: AudioSpec,
+    previous: Option<Arc<dyn Backend>>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl FakeMixer {
+    /// Install the fake and open the device in the default format, with
+    /// 8 channels.
+    pub fn open() -> Result<FakeMixer, MixerError> {
+        FakeMixer::open_spec(AudioSpec {
+            frequency: DEFAULT_FREQUENCY,
+            format: DEFAULT_FORMAT,
+            channels: DEFAULT_CHANNELS,
+        })
+    }
+
+    /// Install the fake and open the device in the format of `spec`.
+    ///
+    /// Waits for fake mixers and test harnesses open on other threads to be
+    /// dropped. Fails if the audio device was opened otherwise.
+    pub fn open_spec(spec: AudioSpec) -> Result<FakeMixer, MixerError> {
+        let lock = lock_ignore_poison(&TEST_LOCK);
+        if OPEN_DEVICES.load(Ordering::SeqCst) != 0 {
+            return Err(MixerError::Sdl("The audio device is already open".to_owned()));
+        }
+        let fake = Arc::new(FakeBackend {
+            state: Mutex::new(State::new()),
+            audio: Mutex::new((None, 0)),
+            audio_unlocked: Condvar::new(),
+        });
+        // Nothing plays without an open device, and the lock keeps other
+        // fakes and harnesses from opening one.
+        let previous = unsafe { backend::set(fake.clone()) };
+        let mut mixer = FakeMixer {
+            backend: fake,
+            device: None,
+            spec,
+            previous,
+            _lock: lock,
+        };
+        mixer.device = Some(open_audio(spec.frequency, spec.format, spec.channels, 512)?);
+        mixer.spec = query_spec()?;
+        Ok(mixer)
+    }
+
+    /// The opened device.
+    pub fn device(&self) -> &AudioDevice {
+        self.device.as_ref().unwrap()
+    }
+
+    /// The format the device was opened in.
+    pub fn spec(&self) -> AudioSpec {
+        self.spec
+    }
+
+    /// The time on the virtual clock, starting at zero.
+    pub fn elapsed(&self) -> Duration {
+        lock_ignore_poison(&self.backend.state).now
+    }
+
+    /// Move the virtual clock forward by `time`, finishing, looping and
+    /// fading channels and music on the way, and mixing each block of 512
+    /// frames once the clock passes its end.
+    ///
+    /// Finished callbacks run before this returns, in the order things
+    /// finished.
+    pub fn advance(&self, time: Duration) {
+        let target = self.backend.with(|state| state.now + time);
+        loop {
+            match self.backend.with(|state| state.step_towards(target)) {
+                Step::Moved => {}
+                Step::Mix(block) => self.backend.mix(block),
+                Step::Done => break,
+            }
+        }
+    }
+
+    /// A silent sample playing for `length`.
+    pub fn chunk(&self, length: Duration) -> Result<Chunk, MixerError> {
+        let frames = self.spec.frames_in(length) as usize;
+        Chunk::from_raw_samples(vec![0; frames * self.spec.bytes_per_frame()])
+    }
+
+    /// Music playing for `length` each time through.
+    ///
+    /// Music loaded otherwise plays until halted.
+    pub fn music(&self, length: Duration) -> Result<Music, MixerError> {
+        Music::from_owned_raw(FakeMusic::boxed(Some(length), MusicType::MusicWav))
+    }
+
+    /// The built-in effects set on `channel`, or on the post-mix stream for
+    /// `AudioDevice::post_channel`.
+    pub fn effects(&self, channel: Channel) -> ChannelEffects {
+        let mut state = lock_ignore_poison(&self.backend.state);
+        state.effects_mut(channel.0).map_or_else(ChannelEffects::default, |effects| *effects)
+    }
+}
+
+impl Drop for FakeMixer {
+    fn drop(&mut self) {
+        if let Some(ref device) = self.device {
+            device.all_channels().halt();
+            device.music().halt();
+        }
+        drop(self.device.take());
+        // The device was closed above.
+        unsafe { backend::replace(self.previous.take()) };
+    }
+}
+
+/// What loaded music points to.
+struct FakeMusic {
+    length: Option<Duration>,
+    music_type: MusicType,
+}
+
+impl FakeMusic {
+    fn boxed(length: Option<Duration>, music_type: MusicType) -> MusicHandle {
+        let music = Box::new(FakeMusic {
+            length,
+            music_type,
+        });
+        MusicHandle::new(Box::into_raw(music) as *mut c_void)
+    }
+}
+
+/// A custom effect registered on a channel.
+#[derive(Debug, Copy, Clone)]
+struct Effect {
+    f: extern "C" fn(c_int, *const c_void, c_int, *const c_void),
+    done: Mix_EffectDone_t,
+    // The argument of both callbacks, by address.
+    arg: usize,
+}
+
+#[derive(Debug)]
+struct Fade {
+    fading: Fading,
+    start: Duration,
+    length: Duration,
+    // The volume faded from or to, and the one restored once done.
+    volume: isize,
+    reset: isize,
+}
+
+#[derive(Debug)]
+struct FakeChannel {
+    // The chunk being played, by address.
+    chunk: Option<usize>,
+    length: Duration,
+    loops: isize,
+    // When the current time through the chunk ends.
+    ends: Duration,
+    start: Duration,
+    expire: Option<Duration>,
+    paused: Option<Duration>,
+    volume: isize,
+    fade: Option<Fade>,
+    tag: isize,
+    effects: ChannelEffects,
+    custom: Vec<Effect>,
+}
+
+impl FakeChannel {
+    fn new() -> FakeChannel {
+        FakeChannel {
+            chunk: None,
+            length: Duration::from_secs(0),
+            loops: 0,
+            ends: Duration::from_secs(0),
+            start: Duration::from_secs(0),
+            expire: None,
+            paused: None,
+            volume: MAX_VOLUME,
+            fade: None,
+            tag: -1,
+            effects: ChannelEffects::default(),
+            custom: Vec::new(),
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        self.chunk.is_some()
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_playing() && self.paused.is_none()
+    }
+
+    /// Fade and loop up to `now`, true if the channel is done playing.
+    fn settle(&mut self, now: Duration) -> bool {
+        if let Some(fade) = self.fade.take() {
+            let elapsed = now - fade.start;
+            if elapsed >= fade.length {
+                self.volume = fade.reset;
+                if fade.fading == Fading::FadingOut {
+                    return true;
+                }
+            } else {
+                let part = if fade.fading == Fading::FadingOut {
+                    fade.length - elapsed
+                } else {
+                    elapsed
+                };
+                self.volume = (fade.volume as u128 * part.as_nanos() /
+                               fade.length.as_nanos()) as isize;
+                self.fade = Some(fade);
+            }
+        }
+        while self.ends <= now && self.loops != 0 {
+            if self.loops > 0 {
+                self.loops -= 1;
+            }
+            self.ends += self.length;
+        }
+        self.ends <= now
+    }
+
+    /// When something next happens to the channel, unless it is paused.
+    fn next_event(&self) -> Option<Duration> {
+        if !self.is_active() {
+            return None;
+        }
+        let fade_end = self.fade.as_ref().map(|fade| fade.start + fade.length);
+        [Some(self.ends), self.expire, fade_end].iter().filter_map(|&time| time).min()
+    }
+}
+
+#[derive(Debug)]
+struct MusicFade {
+    fading: Fading,
+    length: Duration,
+    // Only moves while the music isn't paused.
+    progress: Duration,
+}
+
+#[derive(Debug)]
+struct PlayingMusic {
+    // The music being played, by address.
+    music: usize,
+    length: Option<Duration>,
+    loops: isize,
+    position: Duration,
+    fade: Option<MusicFade>,
+}
+
+impl PlayingMusic {
+    /// Fade and loop up to the current position, true if the music is done.
+    fn settle(&mut self) -> bool {
+        if self.fade.as_ref().map_or(false, |fade| fade.progress >= fade.length) &&
+           self.fade.take().unwrap().fading == Fading::FadingOut {
+            return true;
+        }
+        let length = match self.length {
+            Some(length) => length,
+            None => return false,
+        };
+        while self.position >= length && self.loops != 0 && length > Duration::from_secs(0) {
+            if self.loops > 0 {
+                self.loops -= 1;
+            }
+            self.position -= length;
+        }
+        self.position >= length
+    }
+
+    /// How long until something next happens to the music.
+    fn next_event(&self) -> Option<Duration> {
+        let end = self.length.map(|length| length - cmp::min(length, self.position));
+        let fade_end = self.fade.as_ref().map(|fade| fade.length - cmp::min(fade.length, fade.progress));
+        [end, fade_end].iter().filter_map(|&time| time).min()
+    }
+}
+
+/// A finished callback that is due, run once the state is unlocked.
+#[derive(Debug)]
+enum Finished {
+    Channel(extern "C" fn(c_int), c_int),
+    Music(extern "C" fn()),
+    Effect(extern "C" fn(c_int, *const c_void), c_int, usize),
+}
+
+/// What to mix for a block, taken from the state so the callbacks run
+/// once it is unlocked.
+#[derive(Debug)]
+struct Block {
+    spec: AudioSpec,
+    hook_music: Option<(MixFunc, usize)>,
+    // The custom effects of the channels playing.
+    channels: Vec<(c_int, Vec<Effect>)>,
+    post_effects: Vec<Effect>,
+    post_mix: Option<(MixFunc, usize)>,
+}
+
+/// What `State::step_towards` did.
+#[derive(Debug)]
+enum Step {
+    Moved,
+    Mix(Block),
+    Done,
+}
+
+// Frames mixed at a time, the chunk size every device is opened with.
+const BLOCK_FRAMES: u64 = 512;
+
+#[derive(Debug)]
+struct State {
+    opened: usize,
+    spec: Option<AudioSpec>,
+    now: Duration,
+    channels: Vec<FakeChannel>,
+    reserved: usize,
+    post: ChannelEffects,
+    channel_finished: Option<extern "C" fn(c_int)>,
+    music: Option<PlayingMusic>,
+    music_volume: isize,
+    music_paused: bool,
+    music_finished: Option<extern "C" fn()>,
+    done: Vec<Finished>,
+    blocks: u64,
+    post_custom: Vec<Effect>,
+    post_mix: Option<(MixFunc, usize)>,
+    hook_music: Option<(MixFunc, usize)>,
+    synchro: isize,
+    music_cmd: Option<String>,
+    sound_fonts: Option<String>,
+}
+
+// SDL keeps the error of each thread.
+thread_local!(static ERROR: RefCell<String> = const { RefCell::new(String::new()) });
+
+fn set_error(msg: &str) {
+    ERROR.with(|error| *error.borrow_mut() = msg.to_owned());
+}
+
+fn silence(format: AudioFormat, len: usize) -> Vec<u8> {
+    encode(&vec![0.0; len / format.bytes_per_sample()], format)
+}
+
+fn millis(ms: isize) -> Duration {
+    Duration::from_millis(cmp::max(ms, 0) as u64)
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            opened: 0,
+            spec: None,
+            now: Duration::from_secs(0),
+            channels: Vec::new(),
+            reserved: 0,
+            post: ChannelEffects::default(),
+            channel_finished: None,
+            music: None,
+            music_volume: MAX_VOLUME,
+            music_paused: false,
+            music_finished: None,
+            done: Vec::new(),
+            blocks: 0,
+            post_custom: Vec::new(),
+            post_mix: None,
+            hook_music: None,
+            synchro: 0,
+            music_cmd: None,
+            sound_fonts: None,
+        }
+    }
+
+    fn channel(&mut self, which: isize) -> Option<&mut FakeChannel> {
+        if which < 0 {
+            None
+        } else {
+            self.channels.get_mut(which as usize)
+        }
+    }
+
+    /// The channels `which` refers to, all of them for -1.
+    fn which(&self, which: isize) -> Vec<usize> {
+        if which == -1 {
+            (0..self.channels.len()).collect()
+        } else if which >= 0 && (which as usize) < self.channels.len() {
+            vec![which as usize]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Run the finished callback and remove the effects of `ch`, which
+    /// stopped playing.
+    fn channel_done(&mut self, ch: usize) {
+        if let Some(callback) = self.channel_finished {
+            self.done.push(Finished::Channel(callback, ch as c_int));
+        }
+        self.remove_effects(ch as isize);
+    }
+
+    /// The custom effects of `which`, the post-mix stream for -2.
+    fn custom_mut(&mut self, which: isize) -> Option<&mut Vec<Effect>> {
+        if which == -2 {
+            Some(&mut self.post_custom)
+        } else {
+            self.channel(which).map(|channel| &mut channel.custom)
+        }
+    }
+
+    /// Remove every effect of `which`, running the done callbacks of the
+    /// custom ones.
+    fn remove_effects(&mut self, which: isize) {
+        let removed = self.custom_mut(which).map(mem::take).unwrap_or_default();
+        for effect in removed {
+            if let Some(done) = effect.done {
+                self.done.push(Finished::Effect(done, which as c_int, effect.arg));
+            }
+        }
+        if let Some(effects) = self.effects_mut(which) {
+            *effects = ChannelEffects::default();
+        }
+    }
+
+    fn play(&mut self,
+            which: isize,
+            chunk: *mut Mix_Chunk,
+            loops: isize,
+            fade: Option<isize>,
+            ticks: isize)
+            -> isize {
+        if chunk.is_null() {
+            set_error("Tried to play a NULL chunk");
+            return -1;
+        }
+        let alen = unsafe { (*chunk).alen } as u64;
+        let spec = match self.spec {
+            Some(spec) => spec,
+            None => {
+                set_error("Audio device hasn't been opened");
+                return -1;
+            }
+        };
+        let frame_size = spec.bytes_per_frame() as u64;
+        if alen == 0 || alen % frame_size != 0 {
+            set_error("Tried to play a chunk with a bad frame");
+            return -1;
+        }
+        let ch = if which == -1 {
+            match (self.reserved..self.channels.len()).find(|&ch| !self.channels[ch].is_playing()) {
+                Some(ch) => ch,
+                None => {
+                    set_error("No free channels available");
+                    return -1;
+                }
+            }
+        } else if which >= 0 && (which as usize) < self.channels.len() {
+            which as usize
+        } else {
+            // SDL_mixer returns channels it doesn't have without playing.
+            return which;
+        };
+        if self.channels[ch].is_playing() {
+            self.channel_done(ch);
+        }
+        let now = self.now;
+        let frames = alen / frame_size;
+        let length = Duration::from_nanos(frames * 1_000_000_000 / spec.frequency as u64);
+        let channel = &mut self.channels[ch];
+        channel.chunk = Some(chunk as usize);
+        channel.length = length;
+        channel.loops = loops;
+        channel.ends = now + length;
+        channel.start = now;
+        channel.expire = if ticks > 0 { Some(now + millis(ticks)) } else { None };
+        channel.paused = None;
+        channel.fade = None;
+        if let Some(ms) = fade {
+            channel.fade = Some(Fade {
+                fading: Fading::FadingIn,
+                start: now,
+                length: millis(ms),
+                volume: channel.volume,
+                reset: channel.volume,
+            });
+            channel.volume = 0;
+        }
+        ch as isize
+    }
+
+    /// Stop playing and run the finished callback, restoring the volume if
+    /// the channel was fading.
+    fn halt_channel(&mut self, which: isize) {
+        for ch in self.which(which) {
+            if !self.channels[ch].is_playing() {
+                continue;
+            }
+            self.channel_done(ch);
+            let channel = &mut self.channels[ch];
+            channel.chunk = None;
+            channel.expire = None;
+            if let Some(fade) = channel.fade.take() {
+                channel.volume = fade.reset;
+            }
+        }
+    }
+
+    fn fade_out_channel(&mut self, which: isize, ms: isize) -> isize {
+        let now = self.now;
+        let mut status = 0;
+        for ch in self.which(which) {
+            let channel = &mut self.channels[ch];
+            let fading = channel.fade.as_ref().map(|fade| fade.fading);
+            if !channel.is_playing() || channel.volume <= 0 || fading == Some(Fading::FadingOut) {
+                continue;
+            }
+            let reset = channel.fade.as_ref().map_or(channel.volume, |fade| fade.reset);
+            channel.fade = Some(Fade {
+                fading: Fading::FadingOut,
+                start: now,
+                length: millis(ms),
+                volume: channel.volume,
+                reset,
+            });
+            status += 1;
+        }
+        status
+    }
+
+    fn effects_mut(&mut self, which: isize) -> Option<&mut ChannelEffects> {
+        if self.opened == 0 {
+            set_error("Audio device hasn't been opened");
+            return None;
+        }
+        if which == -2 {
+            return Some(&mut self.post);
+        }
+        match self.channel(which) {
+            Some(channel) => Some(&mut channel.effects),
+            None => {
+                set_error("Invalid channel number");
+                None
+            }
+        }
+    }
+
+    fn set_effects<F>(&mut self, which: isize, set: F) -> bool
+        where F: FnOnce(&mut ChannelEffects)
+    {
+        match self.effects_mut(which) {
+            Some(effects) => {
+                set(effects);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn group_channel(&mut self, which: isize, tag: isize) -> isize {
+        if which == -1 {
+            let last = self.channels.len() as isize - 1;
+            return self.group_channels(0, last, tag);
+        }
+        match self.channel(which) {
+            Some(channel) => {
+                channel.tag = tag;
+                1
+            }
+            None => 0,
+        }
+    }
+
+    fn group_channels(&mut self, from: isize, to: isize, tag: isize) -> isize {
+        (from..to + 1).map(|ch| self.group_channel(ch, tag)).sum()
+    }
+
+    /// The channels tagged `tag`, all of them for -1.
+    fn in_group(&self, tag: isize) -> Vec<usize> {
+        (0..self.channels.len()).filter(|&ch| tag == -1 || self.channels[ch].tag == tag).collect()
+    }
+
+    /// Stop the music and run the finished hook.
+    fn halt_music(&mut self) {
+        if self.music.take().is_some() {
+            if let Some(callback) = self.music_finished {
+                self.done.push(Finished::Music(callback));
+            }
+        }
+    }
+
+    /// Finish, loop and fade what is due now.
+    fn settle(&mut self) {
+        let now = self.now;
+        for ch in 0..self.channels.len() {
+            if !self.channels[ch].is_active() {
+                continue;
+            }
+            if self.channels[ch].expire.map_or(false, |expire| expire <= now) {
+                self.halt_channel(ch as isize);
+            } else if self.channels[ch].settle(now) {
+                self.channel_done(ch);
+                let channel = &mut self.channels[ch];
+                channel.chunk = None;
+                channel.expire = None;
+                channel.fade = None;
+            }
+        }
+        let finished = match self.music {
+            Some(ref mut music) if !self.music_paused => music.settle(),
+            _ => false,
+        };
+        if finished {
+            self.halt_music();
+        }
+    }
+
+    /// The block to mix, if the clock passed the end of the next one.
+    fn block_due(&mut self) -> Option<Block> {
+        let spec = self.spec?;
+        if self.now < spec.duration_of((self.blocks + 1) * BLOCK_FRAMES) {
+            return None;
+        }
+        self.blocks += 1;
+        let channels = self.channels
+                           .iter()
+                           .enumerate()
+                           .filter(|&(_, channel)| channel.is_active() && !channel.custom.is_empty())
+                           .map(|(ch, channel)| (ch as c_int, channel.custom.clone()))
+                           .collect();
+        Some(Block {
+            spec,
+            hook_music: self.hook_music,
+            channels,
+            post_effects: self.post_custom.clone(),
+            post_mix: self.post_mix,
+        })
+    }
+
+    /// Settle what is due, then mix the block that ended or move the clock
+    /// to the next event, block end or `target`, whichever is first.
+    fn step_towards(&mut self, target: Duration) -> Step {
+        self.settle();
+        if let Some(block) = self.block_due() {
+            return Step::Mix(block);
+        }
+        if self.now >= target {
+            return Step::Done;
+        }
+        let mut next = self.channels.iter().filter_map(FakeChannel::next_event).min();
+        if let Some(spec) = self.spec {
+            let block_end = spec.duration_of((self.blocks + 1) * BLOCK_FRAMES);
+            next = Some(next.map_or(block_end, |next| cmp::min(next, block_end)));
+        }
+        if !self.music_paused {
+            let music = self.music.as_ref().and_then(PlayingMusic::next_event);
+            next = [next, music.map(|time| self.now + time)].iter().filter_map(|&time| time).min();
+        }
+        let next = next.map_or(target, |next| cmp::min(next, target));
+        let elapsed = next - self.now;
+        if !self.music_paused {
+            if let Some(ref mut music) = self.music {
+                music.position += elapsed;
+                if let Some(ref mut fade) = music.fade {
+                    fade.progress += elapsed;
+                }
+            }
+        }
+        self.now = next;
+        Step::Moved
+    }
+
+    fn close(&mut self) {
+        self.halt_music();
+        self.halt_channel(-1);
+        for ch in 0..self.channels.len() {
+            self.remove_effects(ch as isize);
+        }
+        self.remove_effects(-2);
+        self.channels.clear();
+        self.reserved = 0;
+        self.spec = None;
+        self.blocks = 0;
+    }
+}
+
+/// The backend installed by `FakeMixer`.
+#[derive(Debug)]
+struct FakeBackend {
+    state: Mutex<State>,
+    // The thread holding the audio lock, and how many times it took it.
+    audio: Mutex<(Option<ThreadId>, usize)>,
+    audio_unlocked: Condvar,
+}
+
+impl FakeBackend {
+    /// Run `f` on the state, then the finished callbacks it made due.
+    fn with<T, F>(&self, f: F) -> T
+        where F: FnOnce(&mut State) -> T
+    {
+        let (ret, done) = {
+            let mut state = lock_ignore_poison(&self.state);
+            let ret = f(&mut state);
+            (ret, mem::take(&mut state.done))
+        };
+        for finished in done {
+            match finished {
+                Finished::Channel(callback, ch) => callback(ch),
+                Finished::Music(callback) => callback(),
+                Finished::Effect(callback, ch, arg) => callback(ch, arg as *const c_void),
+            }
+        }
+        ret
+    }
+
+    /// Mix `block` under the audio lock, as the audio thread would.
+    fn mix(&self, block: Block) {
+        let format = block.spec.format;
+        let len = BLOCK_FRAMES as usize * block.spec.bytes_per_frame();
+        self.lock_audio();
+        let mut stream = silence(format, len);
+        if let Some((f, arg)) = block.hook_music {
+            unsafe { f(arg as *mut c_void, stream.as_mut_ptr(), len as c_int) };
+        }
+        for (ch, effects) in block.channels {
+            let mut channel = silence(format, len);
+            for effect in effects {
+                (effect.f)(ch,
+                           channel.as_mut_ptr() as *const c_void,
+                           len as c_int,
+                           effect.arg as *const c_void);
+            }
+        }
+        for effect in block.post_effects {
+            (effect.f)(-2,
+                       stream.as_mut_ptr() as *const c_void,
+                       len as c_int,
+                       effect.arg as *const c_void);
+        }
+        if let Some((f, arg)) = block.post_mix {
+            unsafe { f(arg as *mut c_void, stream.as_mut_ptr(), len as c_int) };
+        }
+        self.unlock_audio();
+    }
+
+    fn new_chunk(&self, abuf: *const u8, alen: u32) -> *mut Mix_Chunk {
+        if self.with(|state| state.opened) == 0 {
+            set_error("Audio device hasn't been opened");
+            return ptr::null_mut();
+        }
+        Box::into_raw(Box::new(Mix_Chunk {
+            allocated: 0,
+            abuf,
+            alen,
+            volume: MAX_VOLUME as u8,
+        }))
+    }
+}
+
+unsafe fn close_src(src: *mut SDL_RWops, freesrc: bool) {
+    if freesrc && !src.is_null() {
+        ((*src).close)(src);
+    }
+}
+
+impl Backend for FakeBackend {
+    fn linked_version(&self) -> Version {
+        Version {
+            major: 2,
+            minor: 0,
+            patch: 0,
+        }
+    }
+
+    fn init(&self, flags: u32) -> u32 {
+        // Nothing is decoded, so any music loads.
+        flags
+    }
+
+    fn quit(&self) {}
+
+    fn get_num_chunk_decoders(&self) -> isize {
+        1
+    }
+
+    fn get_chunk_decoder(&self, index: isize) -> Option<String> {
+        if index == 0 { Some("WAVE".to_owned()) } else { None }
+    }
+
+    fn get_num_music_decoders(&self) -> isize {
+        1
+    }
+
+    fn get_music_decoder(&self, index: isize) -> Option<String> {
+        if index == 0 { Some("WAVE".to_owned()) } else { None }
+    }
+
+    fn get_error(&self) -> String {
+        ERROR.with(|error| error.borrow().clone())
+    }
+
+    fn set_error(&self, msg: &str) {
+        set_error(msg)
+    }
+
+    fn lock_audio(&self) {
+        let me = thread::current().id();
+        let mut audio = lock_ignore_poison(&self.audio);
+        while audio.0.is_some() && audio.0 != Some(me) {
+            audio = self.audio_unlocked.wait(audio).unwrap_or_else(|e| e.into_inner());
+        }
+        audio.0 = Some(me);
+        audio.1 += 1;
+    }
+
+    fn unlock_audio(&self) {
+        let mut audio = lock_ignore_poison(&self.audio);
+        audio.1 = audio.1.saturating_sub(1);
+        if audio.1 == 0 {
+            audio.0 = None;
+            self.audio_unlocked.notify_all();
+        }
+    }
+
+    fn mix_audio_format(&self, dst: &mut [u8], src: &[u8], format: u16, volume: isize) {
+        // Only host byte order, which is all the crate mixes.
+        let format = match AudioFormat::from_raw(format) {
+            Some(format) if format.bytes_per_sample() == 1 ||
+                            format.is_big_endian() == cfg!(target_endian = "big") => format,
+            _ => return,
+        };
+        let size = format.bytes_per_sample();
+        let len = cmp::min(dst.len(), src.len()) / size * size;
+        let gain = volume.clamp(0, MAX_VOLUME) as f32 / MAX_VOLUME as f32;
+        let mixed: Vec<f32> = decode(&dst[..len], format)
+                                  .into_iter()
+                                  .zip(decode(&src[..len], format))
+                                  .map(|(dst, src)| (dst + src * gain).clamp(-1.0, 1.0))
+                                  .collect();
+        dst[..len].copy_from_slice(&encode(&mixed, format));
+    }
+
+    fn open_audio(&self, frequency: isize, format: u16, channels: isize, _chunk_size: isize) -> isize {
+        let format = match AudioFormat::from_raw(format) {
+            Some(format) if frequency > 0 && channels > 0 => format,
+            _ => {
+                set_error("Unsupported audio format");
+                return -1;
+            }
+        };
+        self.with(|state| {
+            if let Some(spec) = state.spec {
+                if spec.format == format && spec.channels == channels {
+                    state.opened += 1;
+                    return 0;
+                }
+                state.close();
+            }
+            state.spec = Some(AudioSpec {
+                frequency,
+                format,
+                channels,
+            });
+            state.opened = 1;
+            state.channels = (0..CHANNELS).map(|_| FakeChannel::new()).collect();
+            state.music_volume = MAX_VOLUME;
+            0
+        })
+    }
+
+    fn close_audio(&self) {
+        self.with(|state| {
+            if state.opened == 1 {
+                state.close();
+            }
+            state.opened = state.opened.saturating_sub(1);
+        })
+    }
+
+    fn query_spec(&self) -> Option<(isize, u16, isize)> {
+        self.with(|state| state.spec.map(|spec| (spec.frequency, spec.format.to_raw(), spec.channels)))
+    }
+
+    unsafe fn load_wav_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> *mut Mix_Chunk {
+        close_src(src, freesrc);
+        set_error("The fake mixer doesn't decode samples");
+        ptr::null_mut()
+    }
+
+    unsafe fn quick_load_wav(&self, mem: *mut u8) -> *mut Mix_Chunk {
+        let header = slice::from_raw_parts(mem, 12);
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            set_error("Unrecognized audio format");
+            return ptr::null_mut();
+        }
+        let riff_len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let wav = slice::from_raw_parts(mem, riff_len + 8);
+        let mut pos = 12;
+        while pos + 8 <= wav.len() {
+            let len = u32::from_le_bytes([wav[pos + 4], wav[pos + 5], wav[pos + 6], wav[pos + 7]]);
+            if &wav[pos..pos + 4] == b"data" {
+                let len = cmp::min(len as usize, wav.len() - pos - 8);
+                return self.new_chunk(mem.add(pos + 8), len as u32);
+            }
+            pos += 8 + len as usize;
+        }
+        set_error("No data chunk in WAVE file");
+        ptr::null_mut()
+    }
+
+    unsafe fn quick_load_raw(&self, mem: *mut u8, len: u32) -> *mut Mix_Chunk {
+        self.new_chunk(mem, len)
+    }
+
+    unsafe fn free_chunk(&self, chunk: *mut Mix_Chunk) {
+        if chunk.is_null() {
+            return;
+        }
+        self.with(|state| {
+            // Stopped without calling the finished callback, as SDL_mixer does.
+            for channel in &mut state.channels {
+                if channel.chunk == Some(chunk as usize) {
+                    channel.chunk = None;
+                    channel.expire = None;
+                }
+            }
+        });
+        drop(Box::from_raw(chunk));
+    }
+
+    unsafe fn volume_chunk(&self, chunk: *mut Mix_Chunk, volume: isize) -> isize {
+        let previous = (*chunk).volume as isize;
+        if volume >= 0 {
+            (*chunk).volume = cmp::min(volume, MAX_VOLUME) as u8;
+        }
+        previous
+    }
+
+    fn allocate_channels(&self, numchans: isize) -> isize {
+        self.with(|state| {
+            if numchans < 0 {
+                return state.channels.len() as isize;
+            }
+            let numchans = numchans as usize;
+            for ch in numchans..state.channels.len() {
+                state.halt_channel(ch as isize);
+                state.remove_effects(ch as isize);
+            }
+            state.channels.truncate(numchans);
+            while state.channels.len() < numchans {
+                state.channels.push(FakeChannel::new());
+            }
+            numchans as isize
+        })
+    }
+
+    fn volume(&self, channel: isize, volume: isize) -> isize {
+        self.with(|state| {
+            let which = state.which(channel);
+            if which.is_empty() {
+                return 0;
+            }
+            let mut total = 0;
+            for &ch in &which {
+                let channel = &mut state.channels[ch];
+                total += channel.volume;
+                if volume >= 0 {
+                    channel.volume = cmp::min(volume, MAX_VOLUME);
+                }
+            }
+            total / which.len() as isize
+        })
+    }
+
+    unsafe fn play_channel_timed(&self,
+                                 channel: isize,
+                                 chunk: *mut Mix_Chunk,
+                                 loops: isize,
+                                 ticks: isize)
+                                 -> isize {
+        self.with(|state| state.play(channel, chunk, loops, None, ticks))
+    }
+
+    unsafe fn fade_in_channel_timed(&self,
+                                    channel: isize,
+                                    chunk: *mut Mix_Chunk,
+                                    loops: isize,
+                                    ms: isize,
+                                    ticks: isize)
+                                    -> isize {
+        self.with(|state| state.play(channel, chunk, loops, Some(ms), ticks))
+    }
+
+    fn pause(&self, channel: isize) {
+        self.with(|state| {
+            let now = state.now;
+            for ch in state.which(channel) {
+                let channel = &mut state.channels[ch];
+                if channel.is_active() {
+                    channel.paused = Some(now);
+                }
+            }
+        })
+    }
+
+    fn resume(&self, channel: isize) {
+        self.with(|state| {
+            let now = state.now;
+            for ch in state.which(channel) {
+                let channel = &mut state.channels[ch];
+                if let Some(paused) = channel.paused.take() {
+                    // Playback and timed expiry stand still while paused.
+                    channel.ends += now - paused;
+                    if let Some(ref mut expire) = channel.expire {
+                        *expire += now - paused;
+                    }
+                }
+            }
+        })
+    }
+
+    fn halt_channel(&self, channel: isize) {
+        self.with(|state| state.halt_channel(channel))
+    }
+
+    fn expire_channel(&self, channel: isize, ticks: isize) -> isize {
+        self.with(|state| {
+            let now = state.now;
+            let which = state.which(channel);
+            for &ch in &which {
+                state.channels[ch].expire = if ticks > 0 { Some(now + millis(ticks)) } else { None };
+            }
+            which.len() as isize
+        })
+    }
+
+    fn fade_out_channel(&self, channel: isize, ms: isize) -> isize {
+        self.with(|state| state.fade_out_channel(channel, ms))
+    }
+
+    fn playing(&self, channel: isize) -> isize {
+        self.with(|state| {
+            let which = state.which(channel);
+            which.iter().filter(|&&ch| state.channels[ch].is_playing()).count() as isize
+        })
+    }
+
+    fn paused(&self, channel: isize) -> isize {
+        self.with(|state| {
+            let which = state.which(channel);
+            which.iter().filter(|&&ch| state.channels[ch].paused.is_some()).count() as isize
+        })
+    }
+
+    fn fading_channel(&self, channel: isize) -> Fading {
+        self.with(|state| {
+            state.channel(channel)
+                 .and_then(|channel| channel.fade.as_ref().map(|fade| fade.fading))
+                 .unwrap_or(Fading::NoFading)
+        })
+    }
+
+    fn channel_finished(&self, callback: Option<extern "C" fn(c_int)>) {
+        self.with(|state| state.channel_finished = callback)
+    }
+
+    fn set_panning(&self, channel: isize, left: u8, right: u8) -> bool {
+        self.with(|state| state.set_effects(channel, |effects| effects.panning = (left, right)))
+    }
+
+    fn set_distance(&self, channel: isize, distance: u8) -> bool {
+        self.with(|state| state.set_effects(channel, |effects| effects.distance = distance))
+    }
+
+    fn set_position(&self, channel: isize, angle: i16, distance: u8) -> bool {
+        self.with(|state| {
+            state.set_effects(channel, |effects| {
+                effects.angle = angle.rem_euclid(360);
+                effects.distance = distance;
+            })
+        })
+    }
+
+    fn set_reverse_stereo(&self, channel: isize, flip: bool) -> bool {
+        self.with(|state| state.set_effects(channel, |effects| effects.reverse_stereo = flip))
+    }
+
+    fn unregister_all_effects(&self, channel: isize) -> bool {
+        self.with(|state| {
+            if state.effects_mut(channel).is_none() {
+                return false;
+            }
+            state.remove_effects(channel);
+            true
+        })
+    }
+
+    unsafe fn register_effect(&self,
+                              channel: isize,
+                              f: Mix_EffectFunc_t,
+                              d: Mix_EffectDone_t,
+                              arg: *mut c_void)
+                              -> bool {
+        let f = match f {
+            Some(f) => f,
+            None => {
+                set_error("NULL effect callback");
+                return false;
+            }
+        };
+        self.with(|state| {
+            let effect = Effect {
+                f,
+                done: d,
+                arg: arg as usize,
+            };
+            match state.custom_mut(channel) {
+                Some(effects) => effects.push(effect),
+                None => return false,
+            }
+            true
+        })
+    }
+
+    fn unregister_effect(&self, channel: isize, f: Mix_EffectFunc_t) -> bool {
+        let f = f.map(|f| f as usize);
+        self.with(|state| {
+            let effect = match state.custom_mut(channel) {
+                Some(effects) => {
+                    match effects.iter().position(|effect| Some(effect.f as usize) == f) {
+                        Some(pos) => effects.remove(pos),
+                        None => {
+                            set_error("No such effect registered");
+                            return false;
+                        }
+                    }
+                }
+                None => return false,
+            };
+            if let Some(done) = effect.done {
+                state.done.push(Finished::Effect(done, channel as c_int, effect.arg));
+            }
+            true
+        })
+    }
+
+    unsafe fn set_post_mix(&self, f: Option<MixFunc>, arg: *mut c_void) {
+        self.with(|state| state.post_mix = f.map(|f| (f, arg as usize)))
+    }
+
+    fn reserve_channels(&self, num: isize) -> isize {
+        self.with(|state| {
+            state.reserved = cmp::min(cmp::max(num, 0) as usize, state.channels.len());
+            state.reserved as isize
+        })
+    }
+
+    fn group_channel(&self, channel: isize, tag: isize) -> bool {
+        self.with(|state| state.group_channel(channel, tag) == 1)
+    }
+
+    fn group_channels(&self, from: isize, to: isize, tag: isize) -> isize {
+        self.with(|state| state.group_channels(from, to, tag))
+    }
+
+    fn group_count(&self, tag: isize) -> isize {
+        self.with(|state| state.in_group(tag).len() as isize)
+    }
+
+    fn group_available(&self, tag: isize) -> isize {
+        self.with(|state| {
+            let group = state.in_group(tag);
+            group.into_iter().find(|&ch| !state.channels[ch].is_playing()).map_or(-1, |ch| ch as isize)
+        })
+    }
+
+    fn group_oldest(&self, tag: isize) -> isize {
+        self.with(|state| {
+            let mut oldest = (-1, None);
+            for ch in state.in_group(tag) {
+                let channel = &state.channels[ch];
+                if channel.is_playing() && oldest.1.map_or(true, |start| channel.start <= start) {
+                    oldest = (ch as isize, Some(channel.start));
+                }
+            }
+            oldest.0
+        })
+    }
+
+    fn group_newer(&self, tag: isize) -> isize {
+        self.with(|state| {
+            let mut newest = (-1, None);
+            for ch in state.in_group(tag) {
+                let channel = &state.channels[ch];
+                if channel.is_playing() && newest.1.map_or(true, |start| channel.start >= start) {
+                    newest = (ch as isize, Some(channel.start));
+                }
+            }
+            newest.0
+        })
+    }
+
+    fn fade_out_group(&self, tag: isize, ms: isize) -> isize {
+        self.with(|state| {
+            let group: Vec<usize> = (0..state.channels.len())
+                                        .filter(|&ch| state.channels[ch].tag == tag)
+                                        .collect();
+            group.into_iter().map(|ch| state.fade_out_channel(ch as isize, ms)).sum()
+        })
+    }
+
+    fn halt_group(&self, tag: isize) {
+        self.with(|state| {
+            for ch in 0..state.channels.len() {
+                if state.channels[ch].tag == tag {
+                    state.halt_channel(ch as isize);
+                }
+            }
+        })
+    }
+
+    fn load_mus(&self, file: &CStr) -> MusicHandle {
+        let path = file.to_string_lossy();
+        if !Path::new(&*path).is_file() {
+            set_error(&format!("Couldn't open '{}'", path));
+            return MusicHandle::null();
+        }
+        FakeMusic::boxed(None, MusicType::MusicNone)
+    }
+
+    unsafe fn load_mus_rw(&self, src: *mut SDL_RWops, freesrc: bool) -> MusicHandle {
+        close_src(src, freesrc);
+        FakeMusic::boxed(None, MusicType::MusicNone)
+    }
+
+    unsafe fn load_mus_type_rw(&self,
+                               src: *mut SDL_RWops,
+                               music_type: MusicType,
+                               freesrc: bool)
+                               -> MusicHandle {
+        close_src(src, freesrc);
+        FakeMusic::boxed(None, music_type)
+    }
+
+    unsafe fn free_music(&self, music: MusicHandle) {
+        if music.is_null() {
+            return;
+        }
+        self.with(|state| {
+            // Freeing the playing music halts it without calling the hook.
+            if state.music.as_ref().map_or(false, |playing| playing.music == music.as_ptr() as usize) {
+                state.music = None;
+            }
+        });
+        drop(Box::from_raw(music.as_ptr() as *mut FakeMusic));
+    }
+
+    unsafe fn get_music_type(&self, music: MusicHandle) -> MusicType {
+        (*(music.as_ptr() as *const FakeMusic)).music_type
+    }
+
+    unsafe fn play_music(&self, music: MusicHandle, loops: isize) -> isize {
+        self.fade_in_music_pos(music, loops, 0, 0.0)
+    }
+
+    unsafe fn fade_in_music_pos(&self,
+                                music: MusicHandle,
+                                loops: isize,
+                                ms: isize,
+                                position: f64)
+                                -> isize {
+        if music.is_null() {
+            set_error("music parameter was NULL");
+            return -1;
+        }
+        let length = (*(music.as_ptr() as *const FakeMusic)).length;
+        self.with(|state| {
+            // Replaces any music playing without calling the hook.
+            state.synchro = 0;
+            state.music = Some(PlayingMusic {
+                music: music.as_ptr() as usize,
+                length,
+                // Playing once or one loop is the same in SDL_mixer.
+                loops: if loops == 1 { 0 } else { loops },
+                position: Duration::from_secs_f64(position.max(0.0)),
+                fade: if ms > 0 {
+                    Some(MusicFade {
+                        fading: Fading::FadingIn,
+                        length: millis(ms),
+                        progress: Duration::from_secs(0),
+                    })
+                } else {
+                    None
+                },
+            });
+            state.music_paused = false;
+            0
+        })
+    }
+
+    fn volume_music(&self, volume: isize) -> isize {
+        self.with(|state| {
+            let previous = state.music_volume;
+            if volume >= 0 {
+                state.music_volume = cmp::min(volume, MAX_VOLUME);
+            }
+            previous
+        })
+    }
+
+    fn pause_music(&self) {
+        self.with(|state| state.music_paused = true)
+    }
+
+    fn resume_music(&self) {
+        self.with(|state| state.music_paused = false)
+    }
+
+    fn rewind_music(&self) {
+        self.set_music_position(0.0);
+    }
+
+    fn set_music_position(&self, position: f64) -> isize {
+        self.with(|state| {
+            match state.music {
+                Some(ref mut music) => {
+                    music.position = Duration::from_secs_f64(position.max(0.0));
+                    0
+                }
+                None => {
+                    set_error("Music isn't playing");
+                    -1
+                }
+            }
+        })
+    }
+
+    fn halt_music(&self) {
+        self.with(|state| state.halt_music())
+    }
+
+    fn fade_out_music(&self, ms: isize) -> isize {
+        if ms <= 0 {
+            self.halt_music();
+            return 1;
+        }
+        self.with(|state| {
+            let music = match state.music {
+                Some(ref mut music) => music,
+                None => return 0,
+            };
+            let length = millis(ms);
+            // Carry on from the volume a fade already got to.
+            let progress = match music.fade {
+                Some(ref fade) if fade.length > Duration::from_secs(0) => {
+                    let done = cmp::min(fade.progress, fade.length).as_secs_f64() /
+                               fade.length.as_secs_f64();
+                    let done = if fade.fading == Fading::FadingIn { 1.0 - done } else { done };
+                    length.mul_f64(done)
+                }
+                _ => Duration::from_secs(0),
+            };
+            music.fade = Some(MusicFade {
+                fading: Fading::FadingOut,
+                length,
+                progress,
+            });
+            1
+        })
+    }
+
+    fn playing_music(&self) -> bool {
+        self.with(|state| state.music.is_some())
+    }
+
+    fn paused_music(&self) -> bool {
+        self.with(|state| state.music_paused)
+    }
+
+    fn fading_music(&self) -> Fading {
+        self.with(|state| {
+            state.music
+                 .as_ref()
+                 .and_then(|music| music.fade.as_ref().map(|fade| fade.fading))
+                 .unwrap_or(Fading::NoFading)
+        })
+    }
+
+    fn hook_music_finished(&self, callback: Option<extern "C" fn()>) {
+        self.with(|state| state.music_finished = callback)
+    }
+
+    unsafe fn hook_music(&self, f: Option<MixFunc>, arg: *mut c_void) {
+        self.with(|state| state.hook_music = f.map(|f| (f, arg as usize)))
+    }
+
+    fn set_music_cmd(&self, command: &CStr) -> isize {
+        let command = command.to_string_lossy().into_owned();
+        self.with(|state| state.music_cmd = Some(command));
+        0
+    }
+
+    fn set_synchro_value(&self, value: isize) -> isize {
+        self.with(|state| {
+            if state.music.is_none() {
+                return -1;
+            }
+            state.synchro = value;
+            0
+        })
+    }
+
+    fn get_synchro_value(&self) -> isize {
+        self.with(|state| if state.music.is_some() { state.synchro } else { -1 })
+    }
+
+    fn set_sound_fonts(&self, paths: Option<&CStr>) -> bool {
+        let paths = paths.map(|paths| paths.to_string_lossy().into_owned());
+        self.with(|state| state.sound_fonts = paths);
+        true
+    }
+
+    fn get_sound_fonts(&self) -> Option<String> {
+        self.with(|state| state.sound_fonts.clone())
+    }
+
+    unsafe fn each_sound_font(&self, f: SoundFontFunc, data: *mut c_void) -> bool {
+        let paths = match self.get_sound_fonts() {
+            Some(paths) => paths,
+            None => return false,
+        };
+        for path in paths.split(';').filter(|path| !path.is_empty()) {
+            let path = match CString::new(path) {
+                Ok(path) => path,
+                Err(_) => return false,
+            };
+            if f(path.as_ptr(), data) == 0 {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -1,9 +1,40 @@
+#[cfg(feature = "native")]
 extern crate sdl2_sys as sys;
 
-use std::os::raw::{c_uint, c_int, c_char, c_double, c_void};
-use self::sys::rwops::SDL_RWops;
+use std::os::raw::{c_uint, c_int, c_void};
+#[cfg(feature = "native")]
+use std::os::raw::{c_char, c_double};
+#[cfg(feature = "native")]
+pub use self::sys::rwops::SDL_RWops;
+#[cfg(feature = "native")]
 use self::sys::version::SDL_version;
 
+#[cfg(feature = "native")]
+pub use self::sys::audio::{SDL_LockAudio, SDL_UnlockAudio, SDL_MixAudioFormat};
+#[cfg(feature = "native")]
+pub use self::sys::audio::SDL_GetCurrentAudioDriver;
+#[cfg(feature = "native")]
+pub use self::sys::audio::SDL_PauseAudio;
+#[cfg(feature = "native")]
+pub use self::sys::sdl::{SDL_InitSubSystem, SDL_QuitSubSystem, SDL_WasInit, SDL_INIT_AUDIO};
+#[cfg(feature = "native")]
+pub use self::sys::event::{SDL_Event, SDL_PushEvent};
+
+/// The public part of SDL's `SDL_RWops`, for the streams made by `reader`
+/// when SDL isn't linked.
+#[cfg(not(feature = "native"))]
+#[repr(C)]
+pub struct SDL_RWops {
+    pub size: extern "C" fn(context: *mut SDL_RWops) -> i64,
+    pub seek: extern "C" fn(context: *mut SDL_RWops, offset: i64, whence: c_int) -> i64,
+    pub read: extern "C" fn(context: *mut SDL_RWops, ptr: *mut c_void,
+                            size: usize, maxnum: usize) -> usize,
+    pub write: extern "C" fn(context: *mut SDL_RWops, ptr: *const c_void,
+                             size: usize, maxnum: usize) -> usize,
+    pub close: extern "C" fn(context: *mut SDL_RWops) -> c_int,
+    pub type_: u32,
+}
+
 pub type MIX_InitFlags = c_uint;
 pub const MIX_INIT_FLAC: c_uint = 1;
 pub const MIX_INIT_MOD: c_uint = 2;
@@ -44,6 +75,7 @@ pub type Mix_EffectFunc_t = ::std::option::Option<extern "C" fn(arg1: c_int,
 pub type Mix_EffectDone_t = ::std::option::Option<extern "C" fn(arg1: c_int,
                                                                   arg2: *const c_void)
                                                                  >;
+#[cfg(feature = "native")]
 extern "C" {
     pub fn Mix_Linked_Version() -> *const SDL_version;
     pub fn Mix_Init(flags: c_int) -> c_int;
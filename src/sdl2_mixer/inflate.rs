@@ -0,0 +1,338 @@
+//! A small DEFLATE (RFC 1951) decoder, for reading zip archives.
+
+use std::io;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+                              513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+                              24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+                              10, 10, 11, 11, 12, 12, 13, 13];
+// DEFLATE can't expand its input more than that.
+const MAX_RATIO: usize = 1032;
+// The order code length code lengths are stored in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("deflate: {}", msg))
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Bits<'a> {
+        Bits {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or_else(|| invalid("unexpected end"))?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1u32 << n) - 1);
+        self.buf = if n == 32 { 0 } else { self.buf >> n };
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the bits left in the current byte.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..MAX_BITS + 1 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman {
+            counts,
+            symbols,
+        })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..MAX_BITS + 1 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad code"))
+    }
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    let nlen = bits.bits(5)? as usize + 257;
+    let ndist = bits.bits(5)? as usize + 1;
+    let ncode = bits.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(invalid("bad counts"));
+    }
+    let mut clen = [0u8; 19];
+    for &index in &CLEN_ORDER[..ncode] {
+        clen[index] = bits.bits(3)? as u8;
+    }
+    let clen_code = Huffman::new(&clen)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = clen_code.decode(bits)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(invalid("repeat with no first length"));
+                }
+                (lengths[index - 1], 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid("too many lengths"));
+        }
+        for slot in &mut lengths[index..index + repeat] {
+            *slot = len;
+        }
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("no end-of-block code"));
+    }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+fn too_long() -> io::Error {
+    invalid("more output than expected")
+}
+
+fn inflate_block(bits: &mut Bits,
+                 out: &mut Vec<u8>,
+                 size: usize,
+                 lencode: &Huffman,
+                 distcode: &Huffman)
+                 -> io::Result<()> {
+    loop {
+        let symbol = lencode.decode(bits)? as usize;
+        if symbol < 256 {
+            if out.len() == size {
+                return Err(too_long());
+            }
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid("bad length symbol"));
+            }
+            let len = LENGTH_BASE[symbol] as usize +
+                      bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+            let symbol = distcode.decode(bits)? as usize;
+            if symbol >= DIST_BASE.len() {
+                return Err(invalid("bad distance symbol"));
+            }
+            let dist = DIST_BASE[symbol] as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+            if dist > out.len() {
+                return Err(invalid("distance too far back"));
+            }
+            if len > size - out.len() {
+                return Err(too_long());
+            }
+            let start = out.len() - dist;
+            for i in 0..len {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+/// Decompress raw DEFLATE data to exactly `size` bytes, failing as soon as
+/// it would output more.
+pub fn inflate(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut bits = Bits::new(data);
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_RATIO)));
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let pos = bits.pos;
+                if pos + 4 > data.len() {
+                    return Err(invalid("unexpected end"));
+                }
+                let len = data[pos] as usize | (data[pos + 1] as usize) << 8;
+                let nlen = data[pos + 2] as usize | (data[pos + 3] as usize) << 8;
+                if len != !nlen & 0xFFFF {
+                    return Err(invalid("stored block length mismatch"));
+                }
+                if len > size - out.len() {
+                    return Err(too_long());
+                }
+                let stored = data.get(pos + 4..pos + 4 + len)
+                                 .ok_or_else(|| invalid("unexpected end"))?;
+                out.extend_from_slice(stored);
+                bits.pos = pos + 4 + len;
+            }
+            1 => {
+                let (lencode, distcode) = fixed_codes()?;
+                inflate_block(&mut bits, &mut out, size, &lencode, &distcode)?;
+            }
+            2 => {
+                let (lencode, distcode) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, size, &lencode, &distcode)?;
+            }
+            _ => return Err(invalid("bad block type")),
+        }
+        if last {
+            return if out.len() == size {
+                Ok(out)
+            } else {
+                Err(invalid("less output than expected"))
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inflate;
+
+    // Raw DEFLATE streams made with zlib.
+    const STORED: [u8; 12] = [1, 7, 0, 248, 255, 115, 116, 111, 114, 101, 100, 33];
+    const FIXED: [u8; 12] = [243, 72, 205, 201, 201, 215, 81, 200, 64, 162, 20, 1];
+    const DYNAMIC: [u8; 79] = [181, 203, 199, 1, 128, 32, 16, 5, 209, 86, 126, 5, 212, 226, 193,
+                               6, 64, 73, 6, 86, 178, 80, 189, 219, 132, 231, 121, 179, 58, 141,
+                               88, 253, 118, 66, 37, 234, 1, 134, 94, 28, 245, 126, 50, 168, 233,
+                               132, 194, 249, 146, 115, 96, 39, 43, 176, 254, 134, 23, 201, 238,
+                               30, 80, 140, 186, 47, 14, 198, 55, 205, 105, 234, 128, 203, 199, 74,
+                               137, 95, 155, 197, 7];
+    // "first " in a fixed block, an empty stored block, then "second".
+    const BLOCKS: [u8; 20] = [74, 203, 44, 42, 46, 81, 0, 0, 0, 0, 255, 255, 43, 78, 77, 206,
+                              207, 75, 1, 0];
+
+    fn dynamic_text() -> Vec<u8> {
+        let mut text = b"The quick brown fox jumps over the lazy dog. ".repeat(3);
+        text.extend_from_slice(b"Pack my box with five dozen liquor jugs.");
+        text
+    }
+
+    fn error(data: &[u8], size: usize) -> String {
+        inflate(data, size).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(inflate(&STORED, 7).unwrap(), b"stored!");
+    }
+
+    #[test]
+    fn fixed_block() {
+        assert_eq!(inflate(&FIXED, 20).unwrap(), b"Hello, hello, hello!");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        assert_eq!(inflate(&DYNAMIC, 175).unwrap(), dynamic_text());
+    }
+
+    #[test]
+    fn several_blocks() {
+        assert_eq!(inflate(&BLOCKS, 12).unwrap(), b"first second");
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(error(&[], 0), "deflate: unexpected end");
+        assert_eq!(error(&STORED[..8], 7), "deflate: unexpected end");
+        assert_eq!(error(&FIXED[..6], 20), "deflate: unexpected end");
+        assert_eq!(error(&DYNAMIC[..40], 175), "deflate: unexpected end");
+        assert_eq!(error(&BLOCKS[..12], 12), "deflate: unexpected end");
+    }
+
+    #[test]
+    fn invalid_streams() {
+        // A match one byte back before any output.
+        assert_eq!(error(&[3, 2, 0], 3), "deflate: distance too far back");
+        assert_eq!(error(&[7], 0), "deflate: bad block type");
+        let mut stored = STORED;
+        stored[3] = 0;
+        assert_eq!(error(&stored, 7), "deflate: stored block length mismatch");
+    }
+
+    #[test]
+    fn output_is_capped_at_the_size() {
+        assert_eq!(error(&STORED, 6), "deflate: more output than expected");
+        assert_eq!(error(&FIXED, 19), "deflate: more output than expected");
+        assert_eq!(error(&DYNAMIC, 100), "deflate: more output than expected");
+        assert_eq!(error(&FIXED, 21), "deflate: less output than expected");
+        assert_eq!(error(&FIXED, usize::MAX), "deflate: less output than expected");
+    }
+}
@@ -7,21 +7,40 @@
 #[macro_use]
 extern crate bitflags;
 extern crate libc;
+#[cfg(feature = "native")]
 extern crate sdl2;
 
-use std::default;
 use std::fmt;
 use std::ffi::{CString, CStr};
-use std::str::from_utf8;
+use std::fs;
 use std::borrow::ToOwned;
-use std::path::Path;
-use libc::{c_int, uint16_t, c_double, c_uint};
-use sdl2::get_error;
+use std::error::Error;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use libc::{c_char, c_int, c_void};
+#[cfg(feature = "native")]
+use libc::c_uint;
+#[cfg(feature = "native")]
+use sdl2::audio::AudioCVT;
+#[cfg(feature = "native")]
+use sdl2::event::Event;
+#[cfg(feature = "native")]
 use sdl2::rwops::RWops;
-use sdl2::version::Version;
+
+use backend::{Backend, MusicHandle};
+use reader::Source;
 
 // Setup linking for all targets.
-#[cfg(target_os="macos")]
+#[cfg(all(feature = "native", target_os="macos"))]
 mod mac {
     #[cfg(mac_framework)]
     #[link(kind="framework", name="SDL2_mixer")]
@@ -34,7 +53,7 @@ mod mac {
     }
 }
 
-#[cfg(any(target_os="windows", target_os="linux", target_os="freebsd"))]
+#[cfg(all(feature = "native", any(target_os="windows", target_os="linux", target_os="freebsd")))]
 mod others {
     #[link(name="SDL2_mixer")]
     extern "C" {
@@ -43,6 +62,43 @@ mod others {
 
 #[allow(non_camel_case_types, dead_code)]
 mod ffi;
+mod inflate;
+mod reader;
+mod buffer;
+#[cfg(feature = "native")]
+mod driver;
+mod edit;
+mod wav;
+pub mod assets;
+pub mod backend;
+#[cfg(feature = "native")]
+pub mod render;
+#[cfg(all(feature = "testing", feature = "native"))]
+pub mod testing;
+#[cfg(feature = "testing")]
+pub mod fake;
+
+pub use buffer::{AudioBuffer, AudioBufferMut, Frames, FramesMut, Samples, SamplesF32, SamplesMut};
+pub use wav::WavRecorder;
+
+#[cfg(feature = "native")]
+pub use sdl2::version::Version;
+
+/// A library version, as `sdl2::version::Version` when SDL is linked.
+#[cfg(not(feature = "native"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+#[cfg(not(feature = "native"))]
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
 
 // This comes from SDL_audio.h
 #[allow(non_camel_case_types)]
@@ -55,43 +111,252 @@ mod ll {
     pub const AUDIO_S16LSB: uint16_t = 0x8010;
     pub const AUDIO_U16MSB: uint16_t = 0x1010;
     pub const AUDIO_S16MSB: uint16_t = 0x9010;
-    pub const AUDIO_U16: uint16_t = AUDIO_U16LSB;
-    pub const AUDIO_S16: uint16_t = AUDIO_S16LSB;
     pub const AUDIO_S32LSB: uint16_t = 0x8020;
     pub const AUDIO_S32MSB: uint16_t = 0x9020;
-    pub const AUDIO_S32: uint16_t = AUDIO_S32LSB;
     pub const AUDIO_F32LSB: uint16_t = 0x8120;
     pub const AUDIO_F32MSB: uint16_t = 0x9120;
-    pub const AUDIO_F32: uint16_t = AUDIO_F32LSB;
-    pub const AUDIO_U16SYS: uint16_t = AUDIO_U16LSB;
-    pub const AUDIO_S16SYS: uint16_t = AUDIO_S16LSB;
-    pub const AUDIO_S32SYS: uint16_t = AUDIO_S32LSB;
-    pub const AUDIO_F32SYS: uint16_t = AUDIO_F32LSB;
-}
-
-pub type AudioFormat = uint16_t;
-
-pub const AUDIO_U8: AudioFormat = ll::AUDIO_U8;
-pub const AUDIO_S8: AudioFormat = ll::AUDIO_S8;
-pub const AUDIO_U16LSB: AudioFormat = ll::AUDIO_U16LSB;
-pub const AUDIO_S16LSB: AudioFormat = ll::AUDIO_S16LSB;
-pub const AUDIO_U16MSB: AudioFormat = ll::AUDIO_U16MSB;
-pub const AUDIO_S16MSB: AudioFormat = ll::AUDIO_S16MSB;
-pub const AUDIO_U16: AudioFormat = ll::AUDIO_U16;
-pub const AUDIO_S16: AudioFormat = ll::AUDIO_S16;
-pub const AUDIO_S32LSB: AudioFormat = ll::AUDIO_S32LSB;
-pub const AUDIO_S32MSB: AudioFormat = ll::AUDIO_S32MSB;
-pub const AUDIO_S32: AudioFormat = ll::AUDIO_S32;
-pub const AUDIO_F32LSB: AudioFormat = ll::AUDIO_F32LSB;
-pub const AUDIO_F32MSB: AudioFormat = ll::AUDIO_F32MSB;
-pub const AUDIO_F32: AudioFormat = ll::AUDIO_F32;
-pub const AUDIO_U16SYS: AudioFormat = ll::AUDIO_U16SYS;
-pub const AUDIO_S16SYS: AudioFormat = ll::AUDIO_S16SYS;
-pub const AUDIO_S32SYS: AudioFormat = ll::AUDIO_S32SYS;
-pub const AUDIO_F32SYS: AudioFormat = ll::AUDIO_F32SYS;
+
+    pub const AUDIO_MASK_BITSIZE: uint16_t = 0x00FF;
+    pub const AUDIO_MASK_DATATYPE: uint16_t = 0x0100;
+    pub const AUDIO_MASK_ENDIAN: uint16_t = 0x1000;
+    pub const AUDIO_MASK_SIGNED: uint16_t = 0x8000;
+}
+
+/// Audio sample format, as in SDL_audio.h.
+#[repr(u16)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AudioFormat {
+    /// Unsigned 8-bit samples.
+    U8 = ll::AUDIO_U8,
+    /// Signed 8-bit samples.
+    S8 = ll::AUDIO_S8,
+    /// Unsigned 16-bit samples, in little-endian byte order.
+    U16LSB = ll::AUDIO_U16LSB,
+    /// Signed 16-bit samples, in little-endian byte order.
+    S16LSB = ll::AUDIO_S16LSB,
+    /// Unsigned 16-bit samples, in big-endian byte order.
+    U16MSB = ll::AUDIO_U16MSB,
+    /// Signed 16-bit samples, in big-endian byte order.
+    S16MSB = ll::AUDIO_S16MSB,
+    /// 32-bit integer samples, in little-endian byte order.
+    S32LSB = ll::AUDIO_S32LSB,
+    /// 32-bit integer samples, in big-endian byte order.
+    S32MSB = ll::AUDIO_S32MSB,
+    /// 32-bit floating point samples, in little-endian byte order.
+    F32LSB = ll::AUDIO_F32LSB,
+    /// 32-bit floating point samples, in big-endian byte order.
+    F32MSB = ll::AUDIO_F32MSB,
+}
+
+impl AudioFormat {
+    /// Convert an SDL `AUDIO_*` value, `None` if SDL doesn't define it.
+    pub fn from_raw(raw: u16) -> Option<AudioFormat> {
+        match raw {
+            ll::AUDIO_U8 => Some(AudioFormat::U8),
+            ll::AUDIO_S8 => Some(AudioFormat::S8),
+            ll::AUDIO_U16LSB => Some(AudioFormat::U16LSB),
+            ll::AUDIO_S16LSB => Some(AudioFormat::S16LSB),
+            ll::AUDIO_U16MSB => Some(AudioFormat::U16MSB),
+            ll::AUDIO_S16MSB => Some(AudioFormat::S16MSB),
+            ll::AUDIO_S32LSB => Some(AudioFormat::S32LSB),
+            ll::AUDIO_S32MSB => Some(AudioFormat::S32MSB),
+            ll::AUDIO_F32LSB => Some(AudioFormat::F32LSB),
+            ll::AUDIO_F32MSB => Some(AudioFormat::F32MSB),
+            _ => None,
+        }
+    }
+
+    /// The SDL `AUDIO_*` value.
+    pub fn to_raw(self) -> u16 {
+        self as u16
+    }
+
+    /// Size of a sample in bits.
+    pub fn bits(self) -> usize {
+        (self.to_raw() & ll::AUDIO_MASK_BITSIZE) as usize
+    }
+
+    /// Size of a sample in bytes.
+    pub fn bytes_per_sample(self) -> usize {
+        self.bits() / 8
+    }
+
+    /// Whether samples are signed, as all but `U8` and the unsigned 16-bit formats.
+    pub fn is_signed(self) -> bool {
+        self.to_raw() & ll::AUDIO_MASK_SIGNED != 0
+    }
+
+    /// Whether samples are floating point rather than integers.
+    pub fn is_float(self) -> bool {
+        self.to_raw() & ll::AUDIO_MASK_DATATYPE != 0
+    }
+
+    /// Whether multi-byte samples are stored most significant byte first.
+    pub fn is_big_endian(self) -> bool {
+        self.to_raw() & ll::AUDIO_MASK_ENDIAN != 0
+    }
+
+    #[cfg(feature = "native")]
+    fn to_sdl(self) -> sdl2::audio::AudioFormat {
+        use sdl2::audio::AudioFormat as Sdl;
+        match self {
+            AudioFormat::U8 => Sdl::U8,
+            AudioFormat::S8 => Sdl::S8,
+            AudioFormat::U16LSB => Sdl::U16LSB,
+            AudioFormat::S16LSB => Sdl::S16LSB,
+            AudioFormat::U16MSB => Sdl::U16MSB,
+            AudioFormat::S16MSB => Sdl::S16MSB,
+            AudioFormat::S32LSB => Sdl::S32LSB,
+            AudioFormat::S32MSB => Sdl::S32MSB,
+            AudioFormat::F32LSB => Sdl::F32LSB,
+            AudioFormat::F32MSB => Sdl::F32MSB,
+        }
+    }
+}
+
+pub const AUDIO_U8: AudioFormat = AudioFormat::U8;
+pub const AUDIO_S8: AudioFormat = AudioFormat::S8;
+pub const AUDIO_U16LSB: AudioFormat = AudioFormat::U16LSB;
+pub const AUDIO_S16LSB: AudioFormat = AudioFormat::S16LSB;
+pub const AUDIO_U16MSB: AudioFormat = AudioFormat::U16MSB;
+pub const AUDIO_S16MSB: AudioFormat = AudioFormat::S16MSB;
+pub const AUDIO_U16: AudioFormat = AudioFormat::U16LSB;
+pub const AUDIO_S16: AudioFormat = AudioFormat::S16LSB;
+pub const AUDIO_S32LSB: AudioFormat = AudioFormat::S32LSB;
+pub const AUDIO_S32MSB: AudioFormat = AudioFormat::S32MSB;
+pub const AUDIO_S32: AudioFormat = AudioFormat::S32LSB;
+pub const AUDIO_F32LSB: AudioFormat = AudioFormat::F32LSB;
+pub const AUDIO_F32MSB: AudioFormat = AudioFormat::F32MSB;
+pub const AUDIO_F32: AudioFormat = AudioFormat::F32LSB;
+#[cfg(target_endian = "little")]
+pub const AUDIO_U16SYS: AudioFormat = AudioFormat::U16LSB;
+#[cfg(target_endian = "little")]
+pub const AUDIO_S16SYS: AudioFormat = AudioFormat::S16LSB;
+#[cfg(target_endian = "little")]
+pub const AUDIO_S32SYS: AudioFormat = AudioFormat::S32LSB;
+#[cfg(target_endian = "little")]
+pub const AUDIO_F32SYS: AudioFormat = AudioFormat::F32LSB;
+#[cfg(target_endian = "big")]
+pub const AUDIO_U16SYS: AudioFormat = AudioFormat::U16MSB;
+#[cfg(target_endian = "big")]
+pub const AUDIO_S16SYS: AudioFormat = AudioFormat::S16MSB;
+#[cfg(target_endian = "big")]
+pub const AUDIO_S32SYS: AudioFormat = AudioFormat::S32MSB;
+#[cfg(target_endian = "big")]
+pub const AUDIO_F32SYS: AudioFormat = AudioFormat::F32MSB;
+
+/// A sample type matching one of the host byte order audio formats.
+pub trait Sample: Copy + Send + 'static {
+    /// The `AudioFormat` of a buffer made of this sample type.
+    fn audio_format() -> AudioFormat;
+
+    /// The sample value of silence.
+    fn silence() -> Self;
+
+    /// The sample as a float between -1.0 and 1.0.
+    fn to_f32(self) -> f32;
+
+    /// A sample from a float between -1.0 and 1.0, clamping integer samples.
+    fn from_f32(value: f32) -> Self;
+}
+
+/// Scale a float sample to an integer sample range, rounding and clamping.
+fn scale_f32(value: f32, scale: f64) -> f64 {
+    (value as f64 * scale).round().max(-scale).min(scale - 1.0)
+}
+
+impl Sample for u8 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_U8
+    }
+    fn silence() -> u8 {
+        0x80
+    }
+    fn to_f32(self) -> f32 {
+        (self as f32 - 128.0) / 128.0
+    }
+    fn from_f32(value: f32) -> u8 {
+        (scale_f32(value, 128.0) + 128.0) as u8
+    }
+}
+
+impl Sample for i8 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_S8
+    }
+    fn silence() -> i8 {
+        0
+    }
+    fn to_f32(self) -> f32 {
+        self as f32 / 128.0
+    }
+    fn from_f32(value: f32) -> i8 {
+        scale_f32(value, 128.0) as i8
+    }
+}
+
+impl Sample for u16 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_U16SYS
+    }
+    fn silence() -> u16 {
+        0x8000
+    }
+    fn to_f32(self) -> f32 {
+        (self as f32 - 32768.0) / 32768.0
+    }
+    fn from_f32(value: f32) -> u16 {
+        (scale_f32(value, 32768.0) + 32768.0) as u16
+    }
+}
+
+impl Sample for i16 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_S16SYS
+    }
+    fn silence() -> i16 {
+        0
+    }
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+    fn from_f32(value: f32) -> i16 {
+        scale_f32(value, 32768.0) as i16
+    }
+}
+
+impl Sample for i32 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_S32SYS
+    }
+    fn silence() -> i32 {
+        0
+    }
+    fn to_f32(self) -> f32 {
+        (self as f64 / 2147483648.0) as f32
+    }
+    fn from_f32(value: f32) -> i32 {
+        scale_f32(value, 2147483648.0) as i32
+    }
+}
+
+impl Sample for f32 {
+    fn audio_format() -> AudioFormat {
+        AUDIO_F32SYS
+    }
+    fn silence() -> f32 {
+        0.0
+    }
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(value: f32) -> f32 {
+        value
+    }
+}
 
 /// The suggested default is signed 16bit samples in host byte order.
-pub const DEFAULT_FORMAT: AudioFormat = ll::AUDIO_S16SYS;
+pub const DEFAULT_FORMAT: AudioFormat = AUDIO_S16SYS;
 /// Defualt channels: Stereo.
 pub const DEFAULT_CHANNELS: isize = 2;
 /// Good default sample rate in Hz (samples per second) for PC sound cards.
@@ -101,8 +366,7 @@ pub const MAX_VOLUME: isize = 128;
 
 /// Returns the version of the dynamically linked `SDL_mixer` library
 pub fn get_linked_version() -> Version {
-
-    unsafe { Version::from_ll(*ffi::Mix_Linked_Version()) }
+    backend::current().linked_version()
 }
 
 bitflags!(pub flags InitFlag : u32 {
@@ -139,166 +403,614 @@ impl ToString for InitFlag {
     }
 }
 
+/// An error reported by the mixer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MixerError {
+    /// These decoders could not be initialized by `init`.
+    DecoderUnavailable(InitFlag),
+    /// The operation needs `open_audio` to be called first.
+    AudioNotOpen,
+    /// All unreserved channels are busy.
+    NoFreeChannel,
+    /// Loading a file failed.
+    Load { path: PathBuf, reason: String },
+    /// Writing a file failed.
+    Save { path: PathBuf, reason: String },
+    /// The sample type doesn't match the opened audio format.
+    FormatMismatch(AudioFormat),
+    /// The audio data is malformed.
+    InvalidData(String),
+    /// This SDL_mixer build can't decode music of this type.
+    UnsupportedMusicType(MusicType),
+    /// No asset source has an asset of this name.
+    NotFound(String),
+    /// Any other error reported by SDL or SDL_mixer.
+    Sdl(String),
+}
+
+impl MixerError {
+    /// Classify the current SDL error message.
+    fn last() -> MixerError {
+        MixerError::from(backend::current().get_error())
+    }
+
+    /// Attach the path of the file being loaded to an SDL error.
+    fn for_path(self, path: &Path) -> MixerError {
+        match self {
+            MixerError::Sdl(reason) => {
+                MixerError::Load {
+                    path: path.to_owned(),
+                    reason,
+                }
+            }
+            e => e,
+        }
+    }
+}
+
+/// Open a file to load through a reader, so only the backend reads it.
+fn open_file(path: &Path) -> Result<fs::File, MixerError> {
+    fs::File::open(path).map_err(|e| MixerError::Sdl(e.to_string()).for_path(path))
+}
+
+impl From<String> for MixerError {
+    fn from(msg: String) -> MixerError {
+        // SDL_mixer has no error codes, recognize the messages we can act on.
+        match &msg[..] {
+            "Audio device hasn't been opened" => MixerError::AudioNotOpen,
+            "No free channels available" => MixerError::NoFreeChannel,
+            _ => MixerError::Sdl(msg),
+        }
+    }
+}
+
+impl fmt::Display for MixerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MixerError::DecoderUnavailable(flags) => {
+                write!(f, "Could not init: {}", flags.to_string().trim_end())
+            }
+            MixerError::AudioNotOpen => write!(f, "Audio device hasn't been opened"),
+            MixerError::NoFreeChannel => write!(f, "No free channels available"),
+            MixerError::Load { ref path, ref reason } => {
+                write!(f, "Could not load {}: {}", path.display(), reason)
+            }
+            MixerError::Save { ref path, ref reason } => {
+                write!(f, "Could not save {}: {}", path.display(), reason)
+            }
+            MixerError::FormatMismatch(format) => {
+                write!(f, "Sample type does not match audio format {:?}", format)
+            }
+            MixerError::InvalidData(ref msg) => write!(f, "Invalid audio data: {}", msg),
+            MixerError::UnsupportedMusicType(music_type) => {
+                write!(f, "Unsupported music type: {:?}", music_type)
+            }
+            MixerError::NotFound(ref name) => write!(f, "Asset not found: {}", name),
+            MixerError::Sdl(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for MixerError {}
+
 /// Context manager for `sdl2_mixer` to manage init and quit
 pub struct Sdl2MixerContext;
 
 /// Cleans up all dynamically loaded library handles, freeing memory.
 impl Drop for Sdl2MixerContext {
     fn drop(&mut self) {
-        unsafe {
-            ffi::Mix_Quit();
-        }
+        backend::current().quit();
     }
 }
 
 /// Loads dynamic libraries and prepares them for use.  Flags should be
 /// one or more flags from `InitFlag`.
-pub fn init(flags: InitFlag) -> Result<Sdl2MixerContext, String> {
-    let return_flags = unsafe {
-        let ret = ffi::Mix_Init(flags.bits() as c_int);
-        InitFlag::from_bits_truncate(ret as u32)
-    };
+pub fn init(flags: InitFlag) -> Result<Sdl2MixerContext, MixerError> {
+    let return_flags = InitFlag::from_bits_truncate(backend::current().init(flags.bits()));
     // Check if all init flags were set
     if flags.intersects(return_flags) {
         Ok(Sdl2MixerContext)
     } else {
         // Flags not matching won't always set the error message text
-        // according to sdl docs
-        if get_error() == "" {
-            let un_init_flags = return_flags ^ flags;
-            let error_str = &("Could not init: ".to_string() + &un_init_flags.to_string());
-            let _ = sdl2::set_error(error_str);
-        }
-        Err(get_error())
+        // according to sdl docs, so report the flags themselves
+        Err(MixerError::DecoderUnavailable(return_flags ^ flags))
+    }
+}
+
+/// Which of the requested decoders were loaded by `init_with_report`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InitReport {
+    /// The flags passed to init.
+    pub requested: InitFlag,
+    /// The requested flags whose decoder is available.
+    pub enabled: InitFlag,
+}
+
+impl InitReport {
+    /// The requested flags whose decoder could not be loaded.
+    pub fn failed(&self) -> InitFlag {
+        self.requested - self.enabled
+    }
+
+    /// If every requested decoder was loaded.
+    pub fn is_complete(&self) -> bool {
+        self.enabled == self.requested
+    }
+}
+
+/// Loads dynamic libraries like `init`, reporting exactly which of the
+/// requested decoders are available instead of failing.
+pub fn init_with_report(flags: InitFlag) -> (Sdl2MixerContext, InitReport) {
+    let return_flags = InitFlag::from_bits_truncate(backend::current().init(flags.bits()));
+    let report = InitReport {
+        requested: flags,
+        enabled: flags & return_flags,
+    };
+    (Sdl2MixerContext, report)
+}
+
+/// Loads dynamic libraries like `init`, but fails unless every requested
+/// decoder could be loaded.
+pub fn init_strict(flags: InitFlag) -> Result<Sdl2MixerContext, MixerError> {
+    let (context, report) = init_with_report(flags);
+    if report.is_complete() {
+        Ok(context)
+    } else {
+        Err(MixerError::DecoderUnavailable(report.failed()))
+    }
+}
+
+
+// Number of `AudioDevice`s alive, `Mix_OpenAudio` calls nest.
+static OPEN_DEVICES: AtomicUsize = AtomicUsize::new(0);
+
+// The mixer is global, test harnesses and fakes in other threads wait for it.
+#[cfg(any(test, feature = "testing"))]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// The opened audio device, returned by `open_audio`.
+///
+/// Shuts down and cleans up the mixer API when dropped. Channels, groups and
+/// the music player are borrowed from it, so they can't outlive it.
+#[derive(Debug)]
+pub struct AudioDevice {
+    frequency: isize,
+    format: AudioFormat,
+    channels: isize,
+    chunk_size: isize,
+    backend: Arc<dyn Backend>,
+}
+
+impl AudioDevice {
+    /// The actual sample rate in Hz.
+    pub fn frequency(&self) -> isize {
+        self.frequency
+    }
+
+    /// The actual sample format.
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// The actual number of output channels, 1 for mono and 2 for stereo.
+    pub fn channels(&self) -> isize {
+        self.channels
+    }
+
+    /// The number of bytes used per output sample, as requested.
+    pub fn chunk_size(&self) -> isize {
+        self.chunk_size
+    }
+
+    /// Return a channel object.
+    pub fn channel(&self, chan: isize) -> Channel<'_> {
+        Channel(chan, PhantomData)
+    }
+
+    /// Represent for all channels (-1)
+    pub fn all_channels(&self) -> Channel<'_> {
+        Channel(-1, PhantomData)
+    }
+
+    /// This is the MIX_CHANNEL_POST (-2), for effects on the final mix.
+    pub fn post_channel(&self) -> Channel<'_> {
+        Channel(-2, PhantomData)
+    }
+
+    /// Set the number of channels being mixed.
+    pub fn allocate_channels(&self, numchans: isize) -> isize {
+        backend::current().allocate_channels(numchans)
+    }
+
+    /// Reserve num channels from being used when playing samples when
+    /// passing in -1 as a channel number to playback functions.
+    pub fn reserve_channels(&self, num: isize) -> isize {
+        backend::current().reserve_channels(num)
+    }
+
+    /// Returns how many channels are currently playing.
+    pub fn playing_channels(&self) -> isize {
+        backend::current().playing(-1)
+    }
+
+    /// Returns how many channels are currently paused.
+    pub fn paused_channels(&self) -> isize {
+        backend::current().paused(-1)
+    }
+
+    /// Return the channel group with this tag, -1 for all channels.
+    pub fn group(&self, tag: isize) -> Group<'_> {
+        Group(tag, PhantomData)
+    }
+
+    /// Return the music player.
+    pub fn music(&self) -> MusicPlayer<'_> {
+        MusicPlayer(PhantomData)
+    }
+
+    /// Load file for use as a sample.
+    pub fn load_chunk(&self, path: &Path) -> Result<Chunk, MixerError> {
+        Chunk::from_file(path)
+    }
+
+    /// Load music file to use.
+    pub fn load_music(&self, path: &Path) -> Result<Music, MixerError> {
+        Music::from_file(path)
     }
 }
 
+impl Drop for AudioDevice {
+    fn drop(&mut self) {
+        if OPEN_DEVICES.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Mix_CloseAudio frees the channels but keeps their count, leave
+            // none behind so channel functions can't reach the freed ones.
+            self.backend.allocate_channels(0);
+        }
+        self.backend.close_audio();
+        free_finished();
+    }
+}
 
 /// Open the mixer with a certain audio format.
+///
+/// The device stays open until the returned `AudioDevice` is dropped.
 pub fn open_audio(frequency: isize,
                   format: AudioFormat,
                   channels: isize,
                   chunksize: isize)
-                  -> Result<(), String> {
-    let ret = unsafe {
-        ffi::Mix_OpenAudio(frequency as c_int,
-                           format,
-                           channels as c_int,
-                           chunksize as c_int)
+                  -> Result<AudioDevice, MixerError> {
+    let backend = backend::current();
+    if backend.open_audio(frequency, format.to_raw(), channels, chunksize) != 0 {
+        return Err(MixerError::last());
+    }
+    OPEN_DEVICES.fetch_add(1, Ordering::SeqCst);
+    let mut device = AudioDevice {
+        frequency,
+        format,
+        channels,
+        chunk_size: chunksize,
+        backend,
     };
-    if ret == 0 {
-        Ok(())
-    } else {
-        Err(get_error())
-    }
+    // The device may not support the requested format exactly.
+    let spec = query_spec()?;
+    device.frequency = spec.frequency;
+    device.format = spec.format;
+    device.channels = spec.channels;
+    Ok(device)
 }
 
-/// Shutdown and cleanup the mixer API.
-pub fn close_audio() {
-    unsafe { ffi::Mix_CloseAudio() }
+/// The audio format in use by the opened audio device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AudioSpec {
+    /// Sample rate in Hz.
+    pub frequency: isize,
+    pub format: AudioFormat,
+    /// Number of output channels, 1 for mono and 2 for stereo.
+    pub channels: isize,
 }
 
-/// Get the actual audio format in use by the opened audio device.
-pub fn query_spec() -> Result<(isize, AudioFormat, isize), String> {
-    let mut frequency: c_int = 0;
-    let mut format: uint16_t = 0;
-    let mut channels: c_int = 0;
-    let ret = unsafe { ffi::Mix_QuerySpec(&mut frequency, &mut format, &mut channels) };
-    if ret == 0 {
-        Err(get_error())
-    } else {
-        Ok((frequency as isize, format as AudioFormat, channels as isize))
+impl AudioSpec {
+    /// Size in bytes of one sample for every channel.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.format.bytes_per_sample() * self.channels as usize
+    }
+
+    /// Number of frames played in `time`, rounded down.
+    fn frames_in(&self, time: Duration) -> u64 {
+        let frequency = self.frequency as u64;
+        time.as_secs() * frequency + time.subsec_nanos() as u64 * frequency / 1_000_000_000
+    }
+
+    /// How long `frames` frames play, rounded down to the nanosecond.
+    fn duration_of(&self, frames: u64) -> Duration {
+        let frequency = self.frequency as u64;
+        Duration::new(frames / frequency,
+                      ((frames % frequency) * 1_000_000_000 / frequency) as u32)
     }
 }
 
+/// Get the actual audio format in use by the opened audio device.
+pub fn query_spec() -> Result<AudioSpec, MixerError> {
+    let (frequency, format, channels) = match backend::current().query_spec() {
+        Some(spec) => spec,
+        None => return Err(MixerError::AudioNotOpen),
+    };
+    let format = AudioFormat::from_raw(format).ok_or_else(|| {
+        MixerError::Sdl(format!("Unknown audio format {:#06x}", format))
+    })?;
+    Ok(AudioSpec {
+        frequency,
+        format,
+        channels,
+    })
+}
+
 // 4.2 Samples
 
 /// Get the number of sample chunk decoders available from the `Mix_GetChunkDecoder` function.
 pub fn get_chunk_decoders_number() -> isize {
-    unsafe { ffi::Mix_GetNumChunkDecoders() as isize }
+    backend::current().get_num_chunk_decoders()
 }
 
-/// Get the name of the indexed sample chunk decoder.
+/// Get the name of the indexed sample chunk decoder, empty if there is none.
 pub fn get_chunk_decoder(index: isize) -> String {
-    unsafe {
-        let name = ffi::Mix_GetChunkDecoder(index as c_int);
-        from_utf8(CStr::from_ptr(name).to_bytes()).unwrap().to_owned()
+    backend::current().get_chunk_decoder(index).unwrap_or_default()
+}
+
+/// Owns a `Mix_Chunk`, freeing it once the last `Chunk` handle is gone and
+/// no channel is playing it anymore.
+struct ChunkRaw {
+    raw: *mut ffi::Mix_Chunk,
+    backend: Arc<dyn Backend>,
+    // The samples of chunks loaded in place, freed after the chunk.
+    _buffer: Option<Vec<u8>>,
+}
+
+// The chunk is only freed through `Mix_FreeChunk`, which locks the audio device.
+unsafe impl Send for ChunkRaw {}
+unsafe impl Sync for ChunkRaw {}
+
+impl Drop for ChunkRaw {
+    fn drop(&mut self) {
+        unsafe { self.backend.free_chunk(self.raw) }
     }
 }
 
 /// The internal format for an audio chunk.
-#[derive(PartialEq)]
+///
+/// Clones share the same sample data. Channels keep the chunk they play
+/// alive, so it is only freed after the last clone is dropped and every
+/// channel playing it has finished. Chunks released by a finishing channel
+/// are freed on the next play call, or when `unset_channel_finished` is
+/// called or the audio device is closed.
+#[derive(Clone)]
 pub struct Chunk {
-    pub raw: *mut ffi::Mix_Chunk,
-    pub owned: bool,
+    raw: *mut ffi::Mix_Chunk,
+    inner: Arc<ChunkRaw>,
 }
 
-impl Drop for Chunk {
-    fn drop(&mut self) {
-        if self.owned {
-            unsafe { ffi::Mix_FreeChunk(self.raw) }
-        }
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Chunk) -> bool {
+        self.raw == other.raw
     }
 }
 
 impl Chunk {
-    /// Load file for use as a sample.
-    pub fn from_file(path: &Path) -> Result<Chunk, String> {
-        let raw = unsafe { ffi::Mix_LoadWAV_RW(try!(RWops::from_file(path, "rb")).raw(), 0) };
+    fn from_owned_raw(raw: *mut ffi::Mix_Chunk) -> Result<Chunk, MixerError> {
+        Chunk::from_owned_raw_in(raw, None)
+    }
+
+    fn from_owned_raw_in(raw: *mut ffi::Mix_Chunk,
+                         buffer: Option<Vec<u8>>)
+                         -> Result<Chunk, MixerError> {
         if raw.is_null() {
-            Err(get_error())
+            Err(MixerError::last())
         } else {
             Ok(Chunk {
-                raw: raw,
-                owned: true,
+                raw,
+                inner: Arc::new(ChunkRaw {
+                    raw,
+                    backend: backend::current(),
+                    _buffer: buffer,
+                }),
             })
         }
     }
 
+    /// Load a sample from any reader, e.g. an entry of an archive.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Chunk, MixerError> {
+        let src = reader::from_reader(reader);
+        let raw = unsafe { backend::current().load_wav_rw(src.raw(), false) };
+        Chunk::from_owned_raw(raw)
+    }
+
+    /// Use an in-memory WAV file as a sample, without copying or converting it.
+    ///
+    /// The samples must already be in the format returned by `query_spec()`.
+    /// The chunk keeps `wav` alive and plays it in place.
+    pub fn from_wav_bytes(mut wav: Vec<u8>) -> Result<Chunk, MixerError> {
+        check_quick_load_wav(&wav)?;
+        let raw = unsafe { backend::current().quick_load_wav(wav.as_mut_ptr()) };
+        Chunk::from_owned_raw_in(raw, Some(wav))
+    }
+
+    /// Use raw samples as a sample, without copying or converting them.
+    ///
+    /// The samples must already be in the format returned by `query_spec()`,
+    /// interleaved for each output channel. The chunk keeps `samples` alive
+    /// and plays it in place.
+    pub fn from_raw_samples(mut samples: Vec<u8>) -> Result<Chunk, MixerError> {
+        let frame_size = query_spec()?.bytes_per_frame();
+        if samples.len() % frame_size != 0 {
+            return Err(MixerError::InvalidData(format!("{} bytes is not a whole number of frames",
+                                                       samples.len())));
+        }
+        let raw = unsafe {
+            backend::current().quick_load_raw(samples.as_mut_ptr(), samples.len() as u32)
+        };
+        Chunk::from_owned_raw_in(raw, Some(samples))
+    }
+
+    /// Use interleaved 16-bit samples as a sample, converting them from
+    /// `channels` channels at `rate` Hz to the format of the opened device.
+    #[cfg(feature = "native")]
+    pub fn from_samples_i16(samples: &[i16],
+                            channels: isize,
+                            rate: isize)
+                            -> Result<Chunk, MixerError> {
+        Chunk::from_samples(samples, channels, rate)
+    }
+
+    /// Use interleaved float samples between -1.0 and 1.0 as a sample,
+    /// converting them from `channels` channels at `rate` Hz to the format of
+    /// the opened device.
+    #[cfg(feature = "native")]
+    pub fn from_samples_f32(samples: &[f32],
+                            channels: isize,
+                            rate: isize)
+                            -> Result<Chunk, MixerError> {
+        Chunk::from_samples(samples, channels, rate)
+    }
+
+    #[cfg(feature = "native")]
+    fn from_samples<T: Sample>(samples: &[T],
+                               channels: isize,
+                               rate: isize)
+                               -> Result<Chunk, MixerError> {
+        if !(1..=255).contains(&channels) || !(1..=i32::MAX as isize).contains(&rate) {
+            return Err(MixerError::InvalidData(format!("{} channels at {} Hz", channels, rate)));
+        }
+        if samples.len() % channels as usize != 0 {
+            return Err(MixerError::InvalidData(format!("{} samples is not a whole number of frames",
+                                                       samples.len())));
+        }
+        let spec = query_spec()?;
+        let cvt = AudioCVT::new(T::audio_format().to_sdl(),
+                                channels as u8,
+                                rate as i32,
+                                spec.format.to_sdl(),
+                                spec.channels as u8,
+                                spec.frequency as i32)?;
+        let bytes = unsafe {
+            slice::from_raw_parts(samples.as_ptr() as *const u8, mem::size_of_val(samples))
+        };
+        Chunk::from_raw_samples(cvt.convert(bytes.to_vec()))
+    }
+
+    /// Load file for use as a sample.
+    pub fn from_file(path: &Path) -> Result<Chunk, MixerError> {
+        let src = reader::from_reader(open_file(path)?);
+        let raw = unsafe { backend::current().load_wav_rw(src.raw(), false) };
+        Chunk::from_owned_raw(raw).map_err(|e| e.for_path(path))
+    }
+
     /// Set chunk->volume to volume.
     pub fn set_volume(&mut self, volume: isize) -> isize {
-        unsafe { ffi::Mix_VolumeChunk(self.raw, volume as c_int) as isize }
+        unsafe { self.inner.backend.volume_chunk(self.raw, volume) }
     }
 
     /// current volume for the chunk.
     pub fn get_volume(&self) -> isize {
-        unsafe { ffi::Mix_VolumeChunk(self.raw, -1) as isize }
+        unsafe { self.inner.backend.volume_chunk(self.raw, -1) }
+    }
+
+    /// Size of the sample data in bytes.
+    pub fn len_bytes(&self) -> usize {
+        unsafe { (*self.raw).alen as usize }
+    }
+
+    /// Number of sample frames, in the format of the opened audio device
+    /// which samples are converted to when loaded.
+    pub fn frames(&self) -> Result<usize, MixerError> {
+        Ok(self.len_bytes() / query_spec()?.bytes_per_frame())
+    }
+
+    /// How long the sample plays at the opened device's frequency.
+    pub fn duration(&self) -> Result<Duration, MixerError> {
+        let spec = query_spec()?;
+        Ok(spec.duration_of((self.len_bytes() / spec.bytes_per_frame()) as u64))
+    }
+
+    /// The sample data, in the format of the opened audio device.
+    pub fn samples(&self) -> Result<AudioBuffer<'_>, MixerError> {
+        let spec = query_spec()?;
+        AudioBuffer::new(self.bytes(), spec.format, spec.channels as usize)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            let raw = &*self.raw;
+            if raw.abuf.is_null() {
+                &[]
+            } else {
+                slice::from_raw_parts(raw.abuf, raw.alen as usize)
+            }
+        }
+    }
+}
+
+/// Mix_QuickLoad_WAV walks the chunks up to "data" without any bounds check,
+/// so make sure it will find one inside the buffer.
+fn check_quick_load_wav(wav: &[u8]) -> Result<(), MixerError> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(MixerError::InvalidData("not a RIFF/WAVE file".to_owned()));
+    }
+    let mut pos = 12;
+    loop {
+        if pos + 8 > wav.len() {
+            return Err(MixerError::InvalidData("WAV file has no data chunk".to_owned()));
+        }
+        let len = (wav[pos + 4] as usize) | (wav[pos + 5] as usize) << 8 |
+                  (wav[pos + 6] as usize) << 16 |
+                  (wav[pos + 7] as usize) << 24;
+        if len > wav.len() - pos - 8 {
+            return Err(MixerError::InvalidData("WAV chunk exceeds the file".to_owned()));
+        }
+        if &wav[pos..pos + 4] == b"data" {
+            return Ok(());
+        }
+        // It doesn't skip the pad byte after a chunk of odd size either.
+        if len % 2 == 1 {
+            return Err(MixerError::InvalidData("WAV chunk before the data has an odd size"
+                .to_owned()));
+        }
+        pos += 8 + len;
     }
 }
 
 /// Loader trait for `RWops`
+#[cfg(feature = "native")]
 pub trait LoaderRWops {
     /// Load src for use as a sample.
-    fn load_wav(&self) -> Result<Chunk, String>;
+    fn load_wav(&self) -> Result<Chunk, MixerError>;
+
+    /// Load src for use as music.
+    ///
+    /// The music keeps reading from src while it plays, so it borrows src.
+    /// `Music::from_rwops` and `Music::from_bytes` own their source instead.
+    fn load_music(&self) -> Result<MusicRef<'_>, MixerError>;
 
-    fn load_music(&self) -> Result<Music, String>;
+    /// Load src for use as music, decoding it as `music_type` instead of guessing.
+    fn load_music_as(&self, music_type: MusicType) -> Result<MusicRef<'_>, MixerError>;
 }
 
+#[cfg(feature = "native")]
 impl<'a> LoaderRWops for RWops<'a> {
     /// Load src for use as a sample.
-    fn load_wav(&self) -> Result<Chunk, String> {
-        let raw = unsafe { ffi::Mix_LoadWAV_RW(self.raw(), 0) };
-        if raw.is_null() {
-            Err(get_error())
-        } else {
-            Ok(Chunk {
-                raw: raw,
-                owned: true,
-            })
-        }
+    fn load_wav(&self) -> Result<Chunk, MixerError> {
+        let raw = unsafe { backend::current().load_wav_rw(self.raw(), false) };
+        Chunk::from_owned_raw(raw)
     }
 
     /// Load src for use as music.
-    fn load_music(&self) -> Result<Music, String> {
-        let raw = unsafe { ffi::Mix_LoadMUS_RW(self.raw(), 0) };
-        if raw.is_null() {
-            Err(get_error())
-        } else {
-            Ok(Music {
-                raw: raw,
-                owned: true,
-            })
-        }
+    fn load_music(&self) -> Result<MusicRef<'_>, MixerError> {
+        let raw = unsafe { backend::current().load_mus_rw(self.raw(), false) };
+        Music::from_owned_raw(raw).map(MusicRef::new)
+    }
+
+    /// Load src for use as music, decoding it as `music_type` instead of guessing.
+    fn load_music_as(&self, music_type: MusicType) -> Result<MusicRef<'_>, MixerError> {
+        unsafe { Music::load_type_rw(self.raw(), music_type, 0) }.map(MusicRef::new)
     }
 
 }
@@ -315,90 +1027,140 @@ pub enum Fading {
     FadingIn = ffi::MIX_FADING_IN as isize,
 }
 
-/// Sound effect channel.
+impl Fading {
+    #[cfg(feature = "native")]
+    fn from_raw(raw: c_uint) -> Fading {
+        match raw {
+            ffi::MIX_FADING_OUT    => Fading::FadingOut,
+            ffi::MIX_FADING_IN     => Fading::FadingIn,
+            _                      => Fading::NoFading
+        }
+    }
+}
+
+/// Sound effect channel, borrowed from the `AudioDevice` it plays on.
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Channel(isize);
+pub struct Channel<'a>(isize, PhantomData<&'a AudioDevice>);
 
-/// Return a channel object.
-pub fn channel(chan: isize) -> Channel {
-    Channel(chan)
-}
+/// Called with the channel whose playback was halted, see `set_channel_finished`.
+pub type ChannelFinishedFn = Box<dyn FnMut(Channel) + Send>;
 
-/// Set the number of channels being mixed.
-pub fn allocate_channels(numchans: isize) -> isize {
-    unsafe { ffi::Mix_AllocateChannels(numchans as c_int) as isize }
-}
+static CHANNEL_FINISHED: Mutex<Option<ChannelFinishedFn>> = Mutex::new(None);
+
+// The chunk each channel is playing, kept alive until the channel finishes.
+static PLAYING_CHUNKS: Mutex<BTreeMap<isize, Arc<ChunkRaw>>> = Mutex::new(BTreeMap::new());
 
-static mut channel_finished_callback: Option<fn(Channel)> = None;
+// Chunks and music that finished playing. The audio thread must not free
+// them, so they are freed later by `free_finished`.
+static FINISHED: Mutex<Vec<Box<dyn Send>>> = Mutex::new(Vec::new());
+
+/// Free chunks and music that finished playing since the last call.
+///
+/// Runs on the next play call, when a finished callback is unset and when
+/// the audio device is closed.
+fn free_finished() {
+    let finished = mem::take(&mut *lock_ignore_poison(&FINISHED));
+    drop(finished);
+}
 
 extern "C" fn c_channel_finished_callback(ch: c_int) {
-    unsafe {
-        match channel_finished_callback {
-            None => (),
-            Some(cb) => cb(Channel(ch as isize)),
-        }
+    if let Some(chunk) = lock_ignore_poison(&PLAYING_CHUNKS).remove(&(ch as isize)) {
+        lock_ignore_poison(&FINISHED).push(Box::new(chunk));
+    }
+    if let Some(ref mut cb) = *lock_ignore_poison(&CHANNEL_FINISHED) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| cb(Channel(ch as isize, PhantomData))));
     }
 }
 
 /// When channel playback is halted, then the specified `channel_finished` function is called.
-pub fn set_channel_finished(f: fn(Channel)) {
-    unsafe {
-        channel_finished_callback = Some(f);
-        ffi::Mix_ChannelFinished(Some(c_channel_finished_callback as extern "C" fn(ch: c_int)));
-    }
+///
+/// It may run on the audio thread and must not call mixer functions.
+/// A panic inside it is caught and discarded.
+pub fn set_channel_finished(f: ChannelFinishedFn) {
+    let replaced = {
+        let _audio = AudioLock::new();
+        let replaced = lock_ignore_poison(&CHANNEL_FINISHED).replace(f);
+        backend::current().channel_finished(Some(c_channel_finished_callback));
+        replaced
+    };
+    drop(replaced);
 }
 
 /// Unhooks the specified function set before, so no function is called when channel playback is
 /// halted.
 pub fn unset_channel_finished() {
-    unsafe {
-        ffi::Mix_ChannelFinished(None);
-        channel_finished_callback = None;
-    }
+    let removed = {
+        let _audio = AudioLock::new();
+        let removed = lock_ignore_poison(&CHANNEL_FINISHED).take();
+        // The C callback also releases the chunks of finished channels, it
+        // stays installed while any is playing and the next play call needs it.
+        if lock_ignore_poison(&PLAYING_CHUNKS).is_empty() {
+            backend::current().channel_finished(None);
+        }
+        removed
+    };
+    drop(removed);
+    free_finished();
 }
 
-impl Channel {
-    /// Represent for all channels (-1)
-    pub fn all() -> Channel {
-        Channel(-1)
+/// Start playing `chunk` through `play`, keeping it alive on the channel it
+/// ends up on until that channel finishes.
+fn play_chunk<'a, F>(chunk: &Chunk, play: F) -> Result<Channel<'a>, MixerError>
+    where F: FnOnce(&dyn Backend, *mut ffi::Mix_Chunk) -> isize
+{
+    free_finished();
+    // The chunk only makes sense to the backend that loaded it.
+    let backend = &chunk.inner.backend;
+    // Under the audio lock, so the channel can't finish before its chunk is recorded.
+    let _audio = AudioLock::of(backend.clone());
+    backend.channel_finished(Some(c_channel_finished_callback));
+    let ret = play(&**backend, chunk.raw);
+    if ret == -1 {
+        Err(MixerError::from(backend.get_error()))
+    } else {
+        lock_ignore_poison(&PLAYING_CHUNKS).insert(ret, chunk.inner.clone());
+        Ok(Channel(ret, PhantomData))
     }
+}
 
-    /// This is the MIX_CHANNEL_POST (-2)
-    pub fn post() -> Channel {
-        Channel(-2)
+impl<'a> Channel<'a> {
+    /// The channel number, -1 for all channels and -2 for the final mix.
+    pub fn number(self) -> isize {
+        self.0
     }
 
     /// Set the volume for any allocated channel.
     pub fn set_volume(self, volume: isize) -> isize {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_Volume(ch as c_int, volume as c_int) as isize }
+        let Channel(ch, _) = self;
+        backend::current().volume(ch, volume)
     }
 
     /// Returns the channels volume on scale of 0 to 128.
     pub fn get_volume(self) -> isize {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_Volume(ch as c_int, -1) as isize }
+        let Channel(ch, _) = self;
+        backend::current().volume(ch, -1)
     }
 
     /// Play chunk on channel, or if channel is -1, pick the first free unreserved channel.
-    pub fn play(self, chunk: &Chunk, loops: isize) -> Result<Channel, String> {
+    ///
+    /// The channel keeps the chunk alive until it finishes playing.
+    pub fn play(self, chunk: &Chunk, loops: isize) -> Result<Channel<'a>, MixerError> {
         self.play_timed(chunk, loops, -1)
     }
 
-    pub fn play_timed(self, chunk: &Chunk, loops: isize, ticks: isize) -> Result<Channel, String> {
-        let Channel(ch) = self;
-        let ret = unsafe {
-            ffi::Mix_PlayChannelTimed(ch as c_int, chunk.raw, loops as c_int, ticks as c_int)
-        };
-        if ret == -1 {
-            Err(get_error())
-        } else {
-            Ok(Channel(ret as isize))
-        }
+    pub fn play_timed(self,
+                      chunk: &Chunk,
+                      loops: isize,
+                      ticks: isize)
+                      -> Result<Channel<'a>, MixerError> {
+        let Channel(ch, _) = self;
+        play_chunk(chunk, |backend, raw| unsafe {
+            backend.play_channel_timed(ch, raw, loops, ticks)
+        })
     }
 
     /// Play chunk on channel, or if channel is -1, pick the first free unreserved channel.
-    pub fn fade_in(self, chunk: &Chunk, loops: isize, ms: isize) -> Result<Channel, String> {
+    pub fn fade_in(self, chunk: &Chunk, loops: isize, ms: isize) -> Result<Channel<'a>, MixerError> {
         self.fade_in_timed(chunk, loops, ms, -1)
     }
 
@@ -407,101 +1169,80 @@ impl Channel {
                          loops: isize,
                          ms: isize,
                          ticks: isize)
-                         -> Result<Channel, String> {
-        let Channel(ch) = self;
-        let ret = unsafe {
-            ffi::Mix_FadeInChannelTimed(ch as c_int,
-                                        chunk.raw,
-                                        loops as c_int,
-                                        ms as c_int,
-                                        ticks as c_int)
-        };
-        if ret == -1 {
-            Err(get_error())
-        } else {
-            Ok(Channel(ret as isize))
-        }
+                         -> Result<Channel<'a>, MixerError> {
+        let Channel(ch, _) = self;
+        play_chunk(chunk, |backend, raw| unsafe {
+            backend.fade_in_channel_timed(ch, raw, loops, ms, ticks)
+        })
     }
 
     /// Pause channel, or all playing channels if -1 is passed in.
     pub fn pause(self) {
-        let Channel(ch) = self;
-        unsafe {
-            ffi::Mix_Pause(ch as c_int);
-        }
+        let Channel(ch, _) = self;
+        backend::current().pause(ch);
     }
 
     /// Unpause channel, or all playing and paused channels if -1 is passed in.
     pub fn resume(self) {
-        let Channel(ch) = self;
-        unsafe {
-            ffi::Mix_Resume(ch as c_int);
-        }
+        let Channel(ch, _) = self;
+        backend::current().resume(ch);
     }
 
     /// Halt channel playback
     pub fn halt(self) {
-        let Channel(ch) = self;
-        unsafe {
-            ffi::Mix_HaltChannel(ch as c_int);
-        }
+        let Channel(ch, _) = self;
+        backend::current().halt_channel(ch);
     }
 
     /// Halt channel playback, after ticks milliseconds.
     pub fn expire(self, ticks: isize) -> isize {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_ExpireChannel(ch as c_int, ticks as c_int) as isize }
+        let Channel(ch, _) = self;
+        backend::current().expire_channel(ch, ticks)
     }
 
     /// Gradually fade out which channel over ms milliseconds starting from now.
     pub fn fade_out(self, ms: isize) -> isize {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_FadeOutChannel(ch as c_int, ms as c_int) as isize }
+        let Channel(ch, _) = self;
+        backend::current().fade_out_channel(ch, ms)
     }
 
     /// if channel is playing, or not.
     pub fn is_playing(self) -> bool {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_Playing(ch as c_int) != 0 }
+        let Channel(ch, _) = self;
+        backend::current().playing(ch) != 0
     }
 
     ///  if channel is paused, or not.
     pub fn is_paused(self) -> bool {
-        let Channel(ch) = self;
-        unsafe { ffi::Mix_Paused(ch as c_int) != 0 }
+        let Channel(ch, _) = self;
+        backend::current().paused(ch) != 0
     }
 
     /// if channel is fading in, out, or not
     pub fn get_fading(self) -> Fading {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_FadingChannel(ch as c_int) as c_uint };
-        match ret {
-            ffi::MIX_FADING_OUT    => Fading::FadingOut,
-            ffi::MIX_FADING_IN     => Fading::FadingIn,
-            ffi::MIX_NO_FADING | _ => Fading::NoFading
-        }
+        let Channel(ch, _) = self;
+        backend::current().fading_channel(ch)
     }
 
-    /// Get the most recent sample chunk pointer played on channel.
+    /// Get the sample chunk the channel is playing.
+    ///
+    /// Unlike `Mix_GetChunk`, this returns `None` once the channel finished,
+    /// since the chunk may have been freed by then.
     pub fn get_chunk(self) -> Option<Chunk> {
-        let Channel(ch) = self;
-        let raw = unsafe { ffi::Mix_GetChunk(ch as c_int) };
-        if raw.is_null() {
-            None
-        } else {
-            Some(Chunk {
-                raw: raw,
-                owned: false,
-            })
-        }
+        let Channel(ch, _) = self;
+        lock_ignore_poison(&PLAYING_CHUNKS).get(&ch).map(|inner| {
+            Chunk {
+                raw: inner.raw,
+                inner: inner.clone(),
+            }
+        })
     }
 
     /// This removes all effects registered to channel.
-    pub fn unregister_all_effects(self) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_UnregisterAllEffects(ch as c_int) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn unregister_all_effects(self) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().unregister_all_effects(ch) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
@@ -509,22 +1250,20 @@ impl Channel {
 
     /// Sets a panning effect, where left and right is the volume of the left and right channels.
     /// They range from 0 (silence) to 255 (loud).
-    pub fn set_panning(self, left: u8, right: u8) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetPanning(ch as c_int, left, right) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn set_panning(self, left: u8, right: u8) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_panning(ch, left, right) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
     /// Unregisters panning effect.
-    pub fn unset_panning(self) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetPanning(ch as c_int, 255, 255) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn unset_panning(self) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_panning(ch, 255, 255) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
@@ -532,22 +1271,20 @@ impl Channel {
 
     /// This effect simulates a simple attenuation of volume due to distance.
     /// distance ranges from 0 (close/loud) to 255 (far/quiet).
-    pub fn set_distance(self, distance: u8) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetDistance(ch as c_int, distance) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn set_distance(self, distance: u8) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_distance(ch, distance) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
     /// Unregisters distance effect.
-    pub fn unset_distance(self) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetDistance(ch as c_int, 0) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn unset_distance(self) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_distance(ch, 0) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
@@ -556,134 +1293,174 @@ impl Channel {
     /// This effect emulates a simple 3D audio effect.
     /// angle ranges from 0 to 360 degrees going clockwise, where 0 is directly in front.
     /// distance ranges from 0 (close/loud) to 255 (far/quiet).
-    pub fn set_position(self, angle: i16, distance: u8) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetPosition(ch as c_int, angle, distance) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn set_position(self, angle: i16, distance: u8) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_position(ch, angle, distance) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
     /// Unregisters position effect.
-    pub fn unset_position(self) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetPosition(ch as c_int, 0, 0) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn unset_position(self) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_position(ch, 0, 0) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
+    /// Registers a custom effect, called with the channel's audio buffer in the
+    /// format returned by `query_spec()` every time it is mixed.
+    /// `done` is called once the effect is unregistered, or before the error
+    /// is returned if registering fails.
+    ///
+    /// SDL_mixer removes all effects of a channel when it finishes playing, so
+    /// the effect lives until then or until the returned handle is dropped.
+    /// Both callbacks run on the audio thread and must not call mixer functions.
+    pub fn register_effect(self,
+                           effect: EffectFn,
+                           done: Option<EffectDoneFn>)
+                           -> Result<EffectHandle<'a>, MixerError> {
+        let Channel(ch, _) = self;
+        let id = NEXT_EFFECT_ID.fetch_add(1, Ordering::Relaxed);
+        let registered = RegisteredEffect {
+            id,
+            effect,
+            done,
+        };
+        let _audio = AudioLock::new();
+        let first = {
+            let mut effects = lock_ignore_poison(&EFFECTS);
+            let first = !effects.contains_key(&ch);
+            effects.entry(ch).or_default().push(registered);
+            first
+        };
+        if first {
+            let registered = unsafe {
+                backend::current().register_effect(ch,
+                                                   Some(c_effect_callback),
+                                                   Some(c_effect_done),
+                                                   ptr::null_mut())
+            };
+            if !registered {
+                let err = MixerError::last();
+                let removed = lock_ignore_poison(&EFFECTS).remove(&ch);
+                for registered in removed.into_iter().flat_map(|list| list.into_iter()) {
+                    registered.finish();
+                }
+                return Err(err);
+            }
+        }
+        Ok(EffectHandle {
+            channel: self,
+            id,
+        })
+    }
+
+    /// Registers a custom effect like `register_effect`, called with a view
+    /// of the channel's audio buffer that works for any host byte order format.
+    pub fn register_buffer_effect<F>(self,
+                                     mut effect: F,
+                                     done: Option<EffectDoneFn>)
+                                     -> Result<EffectHandle<'a>, MixerError>
+        where F: FnMut(&mut AudioBufferMut) + Send + 'static
+    {
+        let spec = query_spec()?;
+        AudioBufferMut::new(&mut [], spec.format, spec.channels as usize)?;
+        let effect: EffectFn = Box::new(move |buf: &mut [u8]| {
+            if let Ok(mut buf) = AudioBufferMut::new(buf, spec.format, spec.channels as usize) {
+                effect(&mut buf);
+            }
+        });
+        self.register_effect(effect, done)
+    }
+
     /// Simple reverse stereo, swaps left and right channel sound.
     /// true for reverse, false to unregister effect.
-    pub fn set_reverse_stereo(self, flip: bool) -> Result<(), String> {
-        let Channel(ch) = self;
-        let ret = unsafe { ffi::Mix_SetReverseStereo(ch as c_int, flip as c_int) };
-        if ret == 0 {
-            Err(get_error())
+    pub fn set_reverse_stereo(self, flip: bool) -> Result<(), MixerError> {
+        let Channel(ch, _) = self;
+        if !backend::current().set_reverse_stereo(ch, flip) {
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 }
 
-/// Returns how many channels are currently playing.
-pub fn get_playing_channels_number() -> isize {
-    unsafe { ffi::Mix_Playing(-1) as isize }
-}
-
-/// Returns how many channels are currently paused.
-pub fn get_paused_channels_number() -> isize {
-    unsafe { ffi::Mix_Paused(-1) as isize }
-}
-
 // 4.4 Groups
 
-/// Reserve num channels from being used when playing samples when
-/// passing in -1 as a channel number to playback functions.
-pub fn reserve_channels(num: isize) -> isize {
-    unsafe { ffi::Mix_ReserveChannels(num as c_int) as isize }
-}
-
-/// Sound effect channel grouping.
+/// Sound effect channel grouping, borrowed from the `AudioDevice` like
+/// `Channel`. The default group, of all channels, is `AudioDevice::group(-1)`.
 #[derive(Copy, Clone)]
-pub struct Group(isize);
+pub struct Group<'a>(isize, PhantomData<&'a AudioDevice>);
 
-impl default::Default for Group {
-    fn default() -> Group {
-        Group(-1)
-    }
-}
-
-impl Group {
+impl<'a> Group<'a> {
     /// Add channels starting at from up through to to group tag,
     /// or reset it's group to the default group tag (-1).
     pub fn add_channels_range(self, from: isize, to: isize) -> isize {
-        let Group(g) = self;
-        unsafe { ffi::Mix_GroupChannels(from as c_int, to as c_int, g as c_int) as isize }
+        let Group(g, _) = self;
+        backend::current().group_channels(from, to, g)
     }
 
     /// Add which channel to group tag, or reset it's group to the default group tag
-    pub fn add_channel(self, Channel(ch): Channel) -> bool {
-        let Group(g) = self;
-        unsafe { ffi::Mix_GroupChannel(ch as c_int, g as c_int) == 1 }
+    pub fn add_channel(self, Channel(ch, _): Channel) -> bool {
+        let Group(g, _) = self;
+        backend::current().group_channel(ch, g)
     }
 
     /// Count the number of channels in group
     pub fn count(self) -> isize {
-        let Group(g) = self;
-        unsafe { ffi::Mix_GroupCount(g as c_int) as isize }
+        let Group(g, _) = self;
+        backend::current().group_count(g)
     }
 
     /// Find the first available (not playing) channel in group
-    pub fn find_available(self) -> Option<Channel> {
-        let Group(g) = self;
-        let ret = unsafe { ffi::Mix_GroupAvailable(g as c_int) as isize };
+    pub fn find_available(self) -> Option<Channel<'a>> {
+        let Group(g, _) = self;
+        let ret = backend::current().group_available(g);
         if ret == -1 {
             None
         } else {
-            Some(Channel(ret))
+            Some(Channel(ret, PhantomData))
         }
     }
 
     /// Find the oldest actively playing channel in group
-    pub fn find_oldest(self) -> Option<Channel> {
-        let Group(g) = self;
-        let ret = unsafe { ffi::Mix_GroupOldest(g as c_int) as isize };
+    pub fn find_oldest(self) -> Option<Channel<'a>> {
+        let Group(g, _) = self;
+        let ret = backend::current().group_oldest(g);
         if ret == -1 {
             None
         } else {
-            Some(Channel(ret))
+            Some(Channel(ret, PhantomData))
         }
     }
 
     /// Find the newest, most recently started, actively playing channel in group.
-    pub fn find_newest(self) -> Option<Channel> {
-        let Group(g) = self;
-        let ret = unsafe { ffi::Mix_GroupNewer(g as c_int) as isize };
+    pub fn find_newest(self) -> Option<Channel<'a>> {
+        let Group(g, _) = self;
+        let ret = backend::current().group_newer(g);
         if ret == -1 {
             None
         } else {
-            Some(Channel(ret))
+            Some(Channel(ret, PhantomData))
         }
     }
 
     /// Gradually fade out channels in group over some milliseconds starting from now.
     /// Returns the number of channels set to fade out.
     pub fn fade_out(self, ms: isize) -> isize {
-        let Group(g) = self;
-        unsafe { ffi::Mix_FadeOutGroup(g as c_int, ms as c_int) as isize }
+        let Group(g, _) = self;
+        backend::current().fade_out_group(g, ms)
     }
 
     /// Halt playback on all channels in group.
     pub fn halt(self) {
-        let Group(g) = self;
-        unsafe {
-            ffi::Mix_HaltGroup(g as c_int);
-        }
+        let Group(g, _) = self;
+        backend::current().halt_group(g);
     }
 }
 
@@ -691,15 +1468,12 @@ impl Group {
 
 /// Get the number of music decoders available.
 pub fn get_music_decoders_number() -> isize {
-    unsafe { ffi::Mix_GetNumMusicDecoders() as isize }
+    backend::current().get_num_music_decoders()
 }
 
-/// Get the name of the indexed music decoder.
+/// Get the name of the indexed music decoder, empty if there is none.
 pub fn get_music_decoder(index: isize) -> String {
-    unsafe {
-        let name = ffi::Mix_GetMusicDecoder(index as c_int);
-        from_utf8(CStr::from_ptr(name).to_bytes()).unwrap().to_owned()
-    }
+    backend::current().get_music_decoder(index).unwrap_or_default()
 }
 
 /// Music type enumerations
@@ -718,30 +1492,256 @@ pub enum MusicType {
     MusicModPlug = ffi::MUS_MODPLUG as isize,
 }
 
+impl MusicType {
+    #[cfg(feature = "native")]
+    fn from_raw(raw: c_uint) -> MusicType {
+        match raw {
+            ffi::MUS_CMD      => MusicType::MusicCmd,
+            ffi::MUS_WAV      => MusicType::MusicWav,
+            ffi::MUS_MOD      => MusicType::MusicMod,
+            ffi::MUS_MID      => MusicType::MusicMid,
+            ffi::MUS_OGG      => MusicType::MusicOgg,
+            ffi::MUS_MP3      => MusicType::MusicMp3,
+            ffi::MUS_MP3_MAD  => MusicType::MusicMp3Mad,
+            ffi::MUS_FLAC     => MusicType::MusicFlac,
+            ffi::MUS_MODPLUG  => MusicType::MusicModPlug,
+            _                 => MusicType::MusicNone
+        }
+    }
+}
+
+// MIDI soundfonts
+
+/// Use these soundfonts for MIDI music played with FluidSynth, in order of
+/// preference. An empty list goes back to the default soundfonts.
+///
+/// Each path is checked to be a file that can be read first, as SDL_mixer
+/// only notices a bad soundfont when music is loaded.
+pub fn set_soundfonts(paths: &[&Path]) -> Result<(), MixerError> {
+    let mut joined = Vec::with_capacity(paths.len());
+    for path in paths {
+        let is_file = fs::File::open(path)
+                          .and_then(|file| file.metadata())
+                          .map(|metadata| metadata.is_file())
+                          .map_err(|e| {
+                              MixerError::Load {
+                                  path: path.to_path_buf(),
+                                  reason: e.to_string(),
+                              }
+                          })?;
+        if !is_file {
+            return Err(MixerError::Load {
+                path: path.to_path_buf(),
+                reason: "not a file".to_owned(),
+            });
+        }
+        match path.to_str() {
+            Some(s) if !s.contains(';') => joined.push(s),
+            _ => {
+                return Err(MixerError::Load {
+                    path: path.to_path_buf(),
+                    reason: "soundfont paths must be UTF-8 without ';'".to_owned(),
+                })
+            }
+        }
+    }
+    let set = if joined.is_empty() {
+        backend::current().set_sound_fonts(None)
+    } else {
+        let c_paths = CString::new(joined.join(";"))
+                          .map_err(|e| MixerError::InvalidData(e.to_string()))?;
+        backend::current().set_sound_fonts(Some(&c_paths))
+    };
+    if !set {
+        Err(MixerError::last())
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the soundfonts set by `set_soundfonts` or the `SDL_SOUNDFONTS`
+/// environment variable.
+pub fn get_soundfonts() -> Vec<PathBuf> {
+    match backend::current().get_sound_fonts() {
+        Some(paths) => {
+            paths.split(';')
+                 .filter(|path| !path.is_empty())
+                 .map(PathBuf::from)
+                 .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+type SoundFontFn<'a> = &'a mut dyn FnMut(&Path) -> bool;
+
+unsafe extern "C" fn c_each_soundfont(path: *const c_char, data: *mut c_void) -> c_int {
+    let f = &mut *(data as *mut SoundFontFn);
+    let path = CStr::from_ptr(path).to_string_lossy();
+    // Stop on a panic rather than unwinding through SDL_mixer.
+    match panic::catch_unwind(AssertUnwindSafe(|| f(Path::new(&*path)))) {
+        Ok(true) => 1,
+        _ => 0,
+    }
+}
+
+/// Call `f` with each soundfont SDL_mixer would use, until it returns
+/// `false`. Returns whether all soundfonts were visited, which is `false`
+/// too when there are none.
+pub fn each_soundfont<F: FnMut(&Path) -> bool>(mut f: F) -> bool {
+    let mut f: SoundFontFn = &mut f;
+    unsafe {
+        backend::current().each_sound_font(c_each_soundfont,
+                                           &mut f as *mut SoundFontFn as *mut c_void)
+    }
+}
+
 // hooks
-static mut music_finished_hook: Option<fn()> = None;
+
+/// Called when music playback is halted, see `Music::hook_finished`.
+pub type MusicFinishedFn = Box<dyn FnMut() + Send>;
+
+static MUSIC_FINISHED: Mutex<Option<MusicFinishedFn>> = Mutex::new(None);
+
+// The music being played, kept alive until it finishes.
+static PLAYING_MUSIC: Mutex<Option<Arc<MusicRaw>>> = Mutex::new(None);
 
 extern "C" fn c_music_finished_hook() {
-    unsafe {
-        match music_finished_hook {
-            None => (),
-            Some(f) => f(),
+    if let Some(music) = lock_ignore_poison(&PLAYING_MUSIC).take() {
+        lock_ignore_poison(&FINISHED).push(Box::new(music));
+    }
+    if let Some(ref mut f) = *lock_ignore_poison(&MUSIC_FINISHED) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(f));
+    }
+}
+
+/// A source of samples that can replace the music stream, see `Music::hook`.
+pub trait MusicSource: Send + 'static {
+    /// The sample type, which must match the format returned by `query_spec()`.
+    type Sample: Sample;
+
+    /// Fill `buf` with the next interleaved samples of the stream.
+    fn fill(&mut self, buf: &mut [Self::Sample]);
+}
+
+/// The music hook, see `Music::hook`.
+type HookMusicFn = Box<dyn FnMut(&mut [u8]) + Send>;
+
+static HOOK_MUSIC: Mutex<Option<(usize, HookMusicFn)>> = Mutex::new(None);
+static NEXT_HOOK_MUSIC_ID: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn c_hook_music_callback(_udata: *mut c_void, stream: *mut u8, len: c_int) {
+    // SDL_mixer calls the hook even when the music is paused, leaving
+    // the stream silent is what it does for regular music.
+    if backend::current().paused_music() {
+        return;
+    }
+    let buf = slice::from_raw_parts_mut(stream, len as usize);
+    if let Some((_, ref mut hook)) = *lock_ignore_poison(&HOOK_MUSIC) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(buf)));
+    }
+}
+
+fn install_music_hook<S: Send + 'static>(hook: HookMusicFn, source: Arc<Mutex<S>>) -> MusicHook<S> {
+    let id = NEXT_HOOK_MUSIC_ID.fetch_add(1, Ordering::Relaxed);
+    let replaced = {
+        let _audio = AudioLock::new();
+        let replaced = lock_ignore_poison(&HOOK_MUSIC).replace((id, hook));
+        unsafe { backend::current().hook_music(Some(c_hook_music_callback), ptr::null_mut()) };
+        replaced
+    };
+    drop(replaced);
+    MusicHook {
+        id,
+        source: Some(source),
+    }
+}
+
+/// A music source installed by `Music::hook` or `Music::hook_buffer`.
+///
+/// Dropping it stops the source, `unhook` also gives it back.
+pub struct MusicHook<S: Send + 'static> {
+    id: usize,
+    source: Option<Arc<Mutex<S>>>,
+}
+
+impl<S: Send + 'static> MusicHook<S> {
+    /// Stop playing the source and return it.
+    pub fn unhook(mut self) -> S {
+        self.remove();
+        let source = self.source.take().unwrap();
+        match Arc::try_unwrap(source) {
+            Ok(source) => source.into_inner().unwrap_or_else(|e| e.into_inner()),
+            Err(_) => unreachable!("music hook still referenced after removal"),
+        }
+    }
+
+    fn remove(&mut self) {
+        let removed = {
+            let _audio = AudioLock::new();
+            let mut hook_music = lock_ignore_poison(&HOOK_MUSIC);
+            match *hook_music {
+                // Replaced by a later `Music::hook` call.
+                Some((id, _)) if id != self.id => None,
+                _ => {
+                    unsafe { backend::current().hook_music(None, ptr::null_mut()) };
+                    hook_music.take()
+                }
+            }
+        };
+        // Free the hook outside of the audio lock.
+        drop(removed);
+    }
+}
+
+impl<S: Send + 'static> Drop for MusicHook<S> {
+    fn drop(&mut self) {
+        if self.source.is_some() {
+            self.remove();
         }
     }
 }
 
+impl<S: Send + 'static> fmt::Debug for MusicHook<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<MusicHook>")
+    }
+}
+
+/// Owns a `Mix_Music`, freeing it once the last `Music` handle is gone and
+/// it is not playing anymore.
+struct MusicRaw {
+    raw: MusicHandle,
+    backend: Arc<dyn Backend>,
+    // What the music streams from, closed and freed after the music.
+    _src: Option<Source<'static>>,
+}
+
+// The music is only freed through `Mix_FreeMusic`, which locks the audio device.
+unsafe impl Send for MusicRaw {}
+unsafe impl Sync for MusicRaw {}
+
+impl Drop for MusicRaw {
+    fn drop(&mut self) {
+        unsafe { self.backend.free_music(self.raw) };
+    }
+}
+
 /// This is an opaque data type used for Music data.
-#[derive(PartialEq)]
+///
+/// Clones share the same music. Playing music is kept alive until it
+/// finishes, so it is only freed after that and once the last clone is dropped.
+/// Music released when it finishes is freed on the next play call, or when
+/// `Music::unhook_finished` is called or the audio device is closed.
+#[derive(Clone)]
 pub struct Music {
-    pub raw: *mut ffi::Mix_Music,
-    pub owned: bool,
+    raw: MusicHandle,
+    inner: Arc<MusicRaw>,
 }
 
-impl Drop for Music {
-    fn drop(&mut self) {
-        if self.owned {
-            unsafe { ffi::Mix_FreeMusic(self.raw) };
-        }
+impl PartialEq for Music {
+    fn eq(&self, other: &Music) -> bool {
+        self.raw == other.raw
     }
 }
 
@@ -753,199 +1753,958 @@ impl fmt::Debug for Music {
 }
 
 impl Music {
-    /// Load music file to use.
-    pub fn from_file(path: &Path) -> Result<Music, String> {
-        let raw = unsafe {
-            let c_path = CString::new(path.to_str().unwrap()).unwrap();
-            ffi::Mix_LoadMUS(c_path.as_ptr())
-        };
+    fn from_owned_raw(raw: MusicHandle) -> Result<Music, MixerError> {
+        Music::from_owned_raw_in(raw, None)
+    }
+
+    fn from_owned_raw_in(raw: MusicHandle,
+                         src: Option<Source<'static>>)
+                         -> Result<Music, MixerError> {
         if raw.is_null() {
-            Err(get_error())
+            Err(MixerError::last())
         } else {
             Ok(Music {
-                raw: raw,
-                owned: true,
+                raw,
+                inner: Arc::new(MusicRaw {
+                    raw,
+                    backend: backend::current(),
+                    _src: src,
+                }),
             })
         }
     }
 
+    /// Load music from src, which the music keeps reading from while it plays.
+    #[cfg(feature = "native")]
+    pub fn from_rwops(src: RWops<'static>) -> Result<Music, MixerError> {
+        let src = unsafe {
+            let raw = src.raw();
+            mem::forget(src);
+            Source::from_raw(raw)
+        };
+        Music::from_source(src)
+    }
+
+    /// Load music from any reader, e.g. an entry of an archive.
+    ///
+    /// The music streams from the reader while it plays, on the audio thread.
+    pub fn from_reader<R>(reader: R) -> Result<Music, MixerError>
+        where R: Read + Seek + Send + 'static
+    {
+        Music::from_source(reader::from_reader(reader))
+    }
+
+    fn from_source(src: Source<'static>) -> Result<Music, MixerError> {
+        let raw = unsafe { backend::current().load_mus_rw(src.raw(), false) };
+        Music::from_owned_raw_in(raw, Some(src))
+    }
+
+    /// Load music from an in-memory file, e.g. from `include_bytes!`.
+    pub fn from_static(buf: &'static [u8]) -> Result<Music, MixerError> {
+        Music::from_reader(io::Cursor::new(buf))
+    }
+
+    /// Load music from an in-memory file, which the music keeps alive.
+    pub fn from_bytes(buf: Vec<u8>) -> Result<Music, MixerError> {
+        // The RWops owns the buffer and drops it when the music closes it.
+        Music::from_reader(io::Cursor::new(buf))
+    }
+
+    unsafe fn load_type_rw(src: *mut ffi::SDL_RWops,
+                           music_type: MusicType,
+                           freesrc: c_int)
+                           -> Result<Music, MixerError> {
+        match music_type {
+            // These are not decoders, SDL_mixer would only say "Unrecognized music format".
+            MusicType::MusicNone | MusicType::MusicCmd => {
+                if freesrc != 0 {
+                    ((*src).close)(src);
+                }
+                return Err(MixerError::UnsupportedMusicType(music_type));
+            }
+            _ => {}
+        }
+        let raw = backend::current().load_mus_type_rw(src, music_type, freesrc != 0);
+        Music::from_owned_raw(raw).map_err(|e| {
+            match e {
+                MixerError::Sdl(ref msg) if msg == "Unrecognized music format" => {
+                    MixerError::UnsupportedMusicType(music_type)
+                }
+                e => e,
+            }
+        })
+    }
+
+    /// Load music file to use, decoding it as `music_type` whatever its extension.
+    pub fn from_file_as(path: &Path, music_type: MusicType) -> Result<Music, MixerError> {
+        // SDL_mixer closes the file along with the music.
+        let raw_src = reader::from_reader(open_file(path)?).into_raw();
+        unsafe { Music::load_type_rw(raw_src, music_type, 1) }.map_err(|e| e.for_path(path))
+    }
+
+    /// Load music file to use.
+    pub fn from_file(path: &Path) -> Result<Music, MixerError> {
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let raw = backend::current().load_mus(&c_path);
+        Music::from_owned_raw(raw).map_err(|e| e.for_path(path))
+    }
+
+    /// Start playing the music through `play`, keeping it alive until it finishes.
+    fn start<F>(&self, play: F) -> Result<(), MixerError>
+        where F: FnOnce(&dyn Backend, MusicHandle) -> isize
+    {
+        free_finished();
+        let replaced = {
+            // The music only makes sense to the backend that loaded it.
+            let backend = &self.inner.backend;
+            // Under the audio lock, so the music can't finish before it is recorded.
+            let _audio = AudioLock::of(backend.clone());
+            backend.hook_music_finished(Some(c_music_finished_hook));
+            if play(&**backend, self.raw) == -1 {
+                return Err(MixerError::from(backend.get_error()));
+            }
+            // Starting new music halts the previous one without calling the hook.
+            lock_ignore_poison(&PLAYING_MUSIC).replace(self.inner.clone())
+        };
+        drop(replaced);
+        Ok(())
+    }
+
     /// The file format encoding of the music.
     pub fn get_type(&self) -> MusicType {
-        let ret = unsafe { ffi::Mix_GetMusicType(self.raw) as isize } as c_uint;
-        match ret {
-            ffi::MUS_CMD      => MusicType::MusicCmd,
-            ffi::MUS_WAV      => MusicType::MusicWav,
-            ffi::MUS_MOD      => MusicType::MusicMod,
-            ffi::MUS_MID      => MusicType::MusicMid,
-            ffi::MUS_OGG      => MusicType::MusicOgg,
-            ffi::MUS_MP3      => MusicType::MusicMp3,
-            ffi::MUS_MP3_MAD  => MusicType::MusicMp3Mad,
-            ffi::MUS_FLAC     => MusicType::MusicFlac,
-            ffi::MUS_MODPLUG  => MusicType::MusicModPlug,
-            ffi::MUS_NONE | _ => MusicType::MusicNone
-        }
+        unsafe { self.inner.backend.get_music_type(self.raw) }
     }
 
-    /// Play the loaded music loop times through from start to finish.
-    pub fn play(&self, loops: isize) -> Result<(), String> {
-        let ret = unsafe { ffi::Mix_PlayMusic(self.raw, loops as c_int) };
+    /// Setup a command line music player to use to play music.
+    pub fn set_command(command: &str) -> Result<(), MixerError> {
+        let c_command = CString::new(command).unwrap();
+        let ret = backend::current().set_music_cmd(&c_command);
         if ret == -1 {
-            Err(get_error())
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
-    /// Fade in over ms milliseconds of time, the loaded music,
-    /// playing it loop times through from start to finish.
-    pub fn fade_in(&self, loops: isize, ms: isize) -> Result<(), String> {
-        let ret = unsafe { ffi::Mix_FadeInMusic(self.raw, loops as c_int, ms as c_int) };
-        if ret == -1 {
-            Err(get_error())
-        } else {
-            Ok(())
+    /// Play samples from `source` in place of any music, until the returned
+    /// hook is dropped or unhooked. A later call replaces the previous source.
+    ///
+    /// The output follows the music volume and pause state. `fill` runs on the
+    /// audio thread and must not call mixer functions.
+    pub fn hook<S: MusicSource>(source: S) -> Result<MusicHook<S>, MixerError> {
+        let format = query_spec()?.format;
+        if format != S::Sample::audio_format() {
+            return Err(MixerError::FormatMismatch(format));
         }
+        let source = Arc::new(Mutex::new(source));
+        let hooked = source.clone();
+        let mut scratch = Vec::new();
+        let hook: HookMusicFn = Box::new(move |stream: &mut [u8]| {
+            scratch.clear();
+            scratch.resize(stream.len() / mem::size_of::<S::Sample>(), S::Sample::silence());
+            lock_ignore_poison(&hooked).fill(&mut scratch);
+            // Mix into the silent stream to apply the music volume, as
+            // SDL_mixer does for regular music.
+            let samples = unsafe {
+                slice::from_raw_parts(scratch.as_ptr() as *const u8, mem::size_of_val(&scratch[..]))
+            };
+            let backend = backend::current();
+            backend.mix_audio_format(stream, samples, format.to_raw(), backend.volume_music(-1));
+        });
+        Ok(install_music_hook(hook, source))
+    }
+
+    /// Play samples from `source` in place of any music, like `hook`, but
+    /// called with a silent view of the stream that works for any host byte
+    /// order format.
+    pub fn hook_buffer<F>(source: F) -> Result<MusicHook<F>, MixerError>
+        where F: FnMut(&mut AudioBufferMut) + Send + 'static
+    {
+        let spec = query_spec()?;
+        let channels = spec.channels as usize;
+        AudioBufferMut::new(&mut [], spec.format, channels)?;
+        let source = Arc::new(Mutex::new(source));
+        let hooked = source.clone();
+        // `u32`s are aligned for every sample type.
+        let mut scratch: Vec<u32> = Vec::new();
+        let hook: HookMusicFn = Box::new(move |stream: &mut [u8]| {
+            scratch.resize((stream.len() + 3) / 4, 0);
+            let bytes = unsafe {
+                slice::from_raw_parts_mut(scratch.as_mut_ptr() as *mut u8, stream.len())
+            };
+            match AudioBufferMut::new(bytes, spec.format, channels) {
+                Ok(mut buf) => {
+                    buf.silence();
+                    (*lock_ignore_poison(&hooked))(&mut buf);
+                }
+                Err(_) => return,
+            }
+            let backend = backend::current();
+            backend.mix_audio_format(stream, bytes, spec.format.to_raw(), backend.volume_music(-1));
+        });
+        Ok(install_music_hook(hook, source))
     }
 
-    /// Fade in over ms milliseconds of time, from position.
-    pub fn fade_in_from_pos(&self, loops: isize, ms: isize, position: f64) -> Result<(), String> {
-        let ret = unsafe {
-            ffi::Mix_FadeInMusicPos(self.raw, loops as c_int, ms as c_int, position as c_double)
+    /// Sets up a function to be called when music playback is halted.
+    ///
+    /// It may run on the audio thread and must not call mixer functions.
+    /// A panic inside it is caught and discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # // Nothing can mix without SDL_mixer or a backend set.
+    /// # if cfg!(not(feature = "native")) { return; }
+    /// let mut ended = 0;
+    /// sdl2_mixer::Music::hook_finished(Box::new(move || {
+    ///     ended += 1;
+    ///     println!("Music has ended {} times", ended);
+    /// }));
+    /// ```
+    pub fn hook_finished(f: MusicFinishedFn) {
+        let replaced = {
+            let _audio = AudioLock::new();
+            let replaced = lock_ignore_poison(&MUSIC_FINISHED).replace(f);
+            backend::current().hook_music_finished(Some(c_music_finished_hook));
+            replaced
         };
-        if ret == -1 {
-            Err(get_error())
-        } else {
-            Ok(())
+        drop(replaced);
+    }
+
+    /// Deliver a `MixerEvent::MusicFinished` to `sink` whenever music playback
+    /// is halted, in place of a `hook_finished` callback.
+    pub fn hook_finished_events(sink: EventSink) {
+        Music::hook_finished(Box::new(move || sink.deliver(MixerEvent::MusicFinished)));
+    }
+
+    /// A previously set up function would no longer be called when music playback is halted.
+    pub fn unhook_finished() {
+        let removed = {
+            let _audio = AudioLock::new();
+            let removed = lock_ignore_poison(&MUSIC_FINISHED).take();
+            // The C hook also releases the music once it finished, it stays
+            // installed while music is playing.
+            if lock_ignore_poison(&PLAYING_MUSIC).is_none() {
+                backend::current().hook_music_finished(None);
+            }
+            removed
+        };
+        drop(removed);
+        free_finished();
+    }
+}
+
+/// Music streaming from a borrowed `RWops`, see `LoaderRWops::load_music`.
+///
+/// Unlike `Music` it can't be cloned, and dropping it halts the music if it
+/// is playing, so the music never outlives its source.
+#[cfg(feature = "native")]
+#[derive(Debug, PartialEq)]
+pub struct MusicRef<'a> {
+    music: Music,
+    _src: PhantomData<&'a ()>,
+}
+
+#[cfg(feature = "native")]
+impl<'a> MusicRef<'a> {
+    fn new(music: Music) -> MusicRef<'a> {
+        MusicRef {
+            music,
+            _src: PhantomData,
         }
     }
 
-    // FIXME: make these class method?
+    /// The file format encoding of the music.
+    pub fn get_type(&self) -> MusicType {
+        self.music.get_type()
+    }
+}
+
+#[cfg(feature = "native")]
+impl<'a> Drop for MusicRef<'a> {
+    fn drop(&mut self) {
+        // Only the playing music may share the `Mix_Music`. Once that is
+        // released, dropping `self.music` frees it, which halts it.
+        let playing = {
+            let _audio = AudioLock::new();
+            let mut playing = lock_ignore_poison(&PLAYING_MUSIC);
+            let ours = match *playing {
+                Some(ref raw) => Arc::ptr_eq(raw, &self.music.inner),
+                None => false,
+            };
+            if ours {
+                playing.take()
+            } else {
+                None
+            }
+        };
+        drop(playing);
+        free_finished();
+    }
+}
+
+mod sealed {
+    use backend::{Backend, MusicHandle};
+    use MixerError;
+
+    pub trait Sealed {
+        /// Start playing the music through `play`.
+        fn start(&self,
+                 play: &dyn Fn(&dyn Backend, MusicHandle) -> isize)
+                 -> Result<(), MixerError>;
+    }
+}
+
+/// Music that a `MusicPlayer` can play, either a `Music` or a `MusicRef`.
+pub trait Playable: sealed::Sealed {}
+
+impl sealed::Sealed for Music {
+    fn start(&self,
+             play: &dyn Fn(&dyn Backend, MusicHandle) -> isize)
+             -> Result<(), MixerError> {
+        Music::start(self, play)
+    }
+}
+
+impl Playable for Music {}
+
+#[cfg(feature = "native")]
+impl<'a> sealed::Sealed for MusicRef<'a> {
+    fn start(&self,
+             play: &dyn Fn(&dyn Backend, MusicHandle) -> isize)
+             -> Result<(), MixerError> {
+        Music::start(&self.music, play)
+    }
+}
+
+#[cfg(feature = "native")]
+impl<'a> Playable for MusicRef<'a> {}
+
+/// The music player of an `AudioDevice`, borrowed from it by
+/// `AudioDevice::music`. It plays one music at a time.
+#[derive(Debug, Copy, Clone)]
+pub struct MusicPlayer<'a>(PhantomData<&'a AudioDevice>);
+
+impl<'a> MusicPlayer<'a> {
+    /// Play the music loop times through from start to finish.
+    ///
+    /// The music is kept alive until it finishes playing.
+    pub fn play<M: Playable>(self, music: &M, loops: isize) -> Result<(), MixerError> {
+        music.start(&|backend, raw| unsafe { backend.play_music(raw, loops) })
+    }
+
+    /// Fade in the music over ms milliseconds of time,
+    /// playing it loop times through from start to finish.
+    pub fn fade_in<M: Playable>(self,
+                                music: &M,
+                                loops: isize,
+                                ms: isize)
+                                -> Result<(), MixerError> {
+        music.start(&|backend, raw| unsafe { backend.fade_in_music_pos(raw, loops, ms, 0.0) })
+    }
+
+    /// Fade in over ms milliseconds of time, from position.
+    pub fn fade_in_from_pos<M: Playable>(self,
+                                         music: &M,
+                                         loops: isize,
+                                         ms: isize,
+                                         position: f64)
+                                         -> Result<(), MixerError> {
+        music.start(&|backend, raw| unsafe {
+            backend.fade_in_music_pos(raw, loops, ms, position)
+        })
+    }
+
     /// Returns current volume
-    pub fn get_volume() -> isize {
-        unsafe { ffi::Mix_VolumeMusic(-1) as isize }
+    pub fn get_volume(self) -> isize {
+        backend::current().volume_music(-1)
     }
 
     /// Set the volume on a scale of 0 to 128.
     /// Values greater than 128 will use 128.
-    pub fn set_volume(volume: isize) {
+    pub fn set_volume(self, volume: isize) {
         // This shouldn't return anything. Use get_volume instead
-        let _ = unsafe { ffi::Mix_VolumeMusic(volume as c_int) as isize };
+        let _ = backend::current().volume_music(volume);
     }
 
     /// Pause the music playback.
-    pub fn pause() {
-        unsafe {
-            ffi::Mix_PauseMusic();
-        }
+    pub fn pause(self) {
+        backend::current().pause_music();
     }
 
     /// Unpause the music.
-    pub fn resume() {
-        unsafe {
-            ffi::Mix_ResumeMusic();
-        }
+    pub fn resume(self) {
+        backend::current().resume_music();
     }
 
     /// Rewind the music to the start.
-    pub fn rewind() {
-        unsafe {
-            ffi::Mix_RewindMusic();
-        }
+    pub fn rewind(self) {
+        backend::current().rewind_music();
     }
 
     /// Set the position of the currently playing music.
-    pub fn set_pos(position: f64) -> Result<(), String> {
-        let ret = unsafe { ffi::Mix_SetMusicPosition(position as c_double) };
+    pub fn set_pos(self, position: f64) -> Result<(), MixerError> {
+        let ret = backend::current().set_music_position(position);
         if ret == -1 {
-            Err(get_error())
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
-    /// Setup a command line music player to use to play music.
-    pub fn set_command(command: &str) -> Result<(), String> {
-        let ret = unsafe {
-            let c_command = CString::new(command).unwrap();
-            ffi::Mix_SetMusicCMD(c_command.as_ptr())
-        };
+    /// Halt playback of music.
+    pub fn halt(self) {
+        backend::current().halt_music();
+    }
+
+    /// Gradually fade out the music over ms milliseconds starting from now.
+    pub fn fade_out(self, ms: isize) -> Result<(), MixerError> {
+        let ret = backend::current().fade_out_music(ms);
         if ret == -1 {
-            Err(get_error())
+            Err(MixerError::last())
         } else {
             Ok(())
         }
     }
 
-    /// Halt playback of music.
-    pub fn halt() {
-        unsafe {
-            ffi::Mix_HaltMusic();
+    /// If music is actively playing, or not.
+    pub fn is_playing(self) -> bool {
+        backend::current().playing_music()
+    }
+
+    /// If music is paused, or not.
+    pub fn is_paused(self) -> bool {
+        backend::current().paused_music()
+    }
+
+    /// If music is fading, or not.
+    pub fn get_fading(self) -> Fading {
+        backend::current().fading_music()
+    }
+
+    /// Get the synchro value of the music player, set by effect commands in
+    /// tracker modules. `None` if the player doesn't support it.
+    pub fn synchro_value(self) -> Option<isize> {
+        match backend::current().get_synchro_value() {
+            -1 => None,
+            value => Some(value),
         }
     }
 
-    /// Gradually fade out the music over ms milliseconds starting from now.
-    pub fn fade_out(ms: isize) -> Result<(), String> {
-        let ret = unsafe { ffi::Mix_FadeOutMusic(ms as c_int) };
+    /// Set the synchro value of the music player.
+    pub fn set_synchro_value(self, value: isize) -> Result<(), MixerError> {
+        let ret = backend::current().set_synchro_value(value);
         if ret == -1 {
-            Err(get_error())
+            // Players without synchro support fail without setting an error.
+            Err(MixerError::Sdl("Synchro values are not supported by the music player"
+                                    .to_owned()))
         } else {
             Ok(())
         }
     }
+}
 
-    // TODO: Mix_HookMusic
-    // TODO: Mix_GetMusicHookData
+/// Watches `MusicPlayer::synchro_value` for changes, delivering a
+/// `MixerEvent::SynchroChanged` for each new value.
+///
+/// SDL_mixer has no callback for this, so call `poll` once a frame with the
+/// device's `MusicPlayer`.
+#[derive(Debug, Clone)]
+pub struct SynchroWatcher {
+    sink: EventSink,
+    last: Option<isize>,
+}
 
-    /// Sets up a function to be called when music playback is halted.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// fn after_music() {
-    ///     println!("Music has ended");
-    /// }
-    ///
-    /// sdl2_mixer::Music::hook_finished(after_music);
-    /// ```
-    pub fn hook_finished(f: fn()) {
-        unsafe {
-            music_finished_hook = Some(f);
-            ffi::Mix_HookMusicFinished(Some(c_music_finished_hook as extern "C" fn()));
+impl SynchroWatcher {
+    pub fn new(sink: EventSink) -> SynchroWatcher {
+        SynchroWatcher {
+            sink,
+            last: None,
         }
     }
 
-    /// A previously set up function would no longer be called when music playback is halted.
-    pub fn unhook_finished() {
-        unsafe {
-            ffi::Mix_HookMusicFinished(None);
-            // unset from c, then rust, to avoid race condiction
-            music_finished_hook = None;
-        }
+    /// The value seen by the last `poll`.
+    pub fn last(&self) -> Option<isize> {
+        self.last
     }
 
-    /// If music is actively playing, or not.
-    pub fn is_playing() -> bool {
-        unsafe { ffi::Mix_PlayingMusic() == 1 }
+    /// Check the synchro value of `player`, delivering it if it changed.
+    pub fn poll(&mut self, player: MusicPlayer) -> SynchroChange {
+        let value = player.synchro_value();
+        if value == self.last {
+            return SynchroChange::Unchanged;
+        }
+        self.last = value;
+        match value {
+            Some(value) => {
+                self.sink.deliver(MixerEvent::SynchroChanged(value));
+                SynchroChange::Changed(value)
+            }
+            None => SynchroChange::Unsupported,
+        }
     }
+}
 
-    /// If music is paused, or not.
-    pub fn is_paused() -> bool {
-        unsafe { ffi::Mix_PausedMusic() == 1 }
+/// What `SynchroWatcher::poll` saw.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SynchroChange {
+    /// The value is the same as on the last poll.
+    Unchanged,
+    /// The value changed to this one.
+    Changed(isize),
+    /// The music player stopped supporting synchro values, e.g. after
+    /// switching to music of another type.
+    Unsupported,
+}
+
+// Mixer events
+
+// `code` of the SDL user events pushed by `EventSink::Sdl`.
+#[cfg(feature = "native")]
+const USER_EVENT_CHANNEL_FINISHED: i32 = 0;
+#[cfg(feature = "native")]
+const USER_EVENT_MUSIC_FINISHED: i32 = 1;
+#[cfg(feature = "native")]
+const USER_EVENT_SYNCHRO_CHANGED: i32 = 2;
+
+/// A mixer notification, delivered outside of the audio thread by an `EventSink`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MixerEvent {
+    /// Playback on the channel with this number was halted, see `AudioDevice::channel`.
+    ChannelFinished(isize),
+    /// Music playback was halted.
+    MusicFinished,
+    /// The music's synchro value changed, see `SynchroWatcher`.
+    SynchroChanged(isize),
+}
+
+impl MixerEvent {
+    /// Decode an event pushed by `EventSink::Sdl(type_)`.
+    #[cfg(feature = "native")]
+    pub fn from_sdl_event(event: &Event, type_: u32) -> Option<MixerEvent> {
+        match *event {
+            Event::User { type_: t, code, data1, .. } if t == type_ => {
+                match code {
+                    USER_EVENT_CHANNEL_FINISHED => {
+                        Some(MixerEvent::ChannelFinished(data1 as isize))
+                    }
+                    USER_EVENT_MUSIC_FINISHED => Some(MixerEvent::MusicFinished),
+                    USER_EVENT_SYNCHRO_CHANGED => {
+                        Some(MixerEvent::SynchroChanged(data1 as isize))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
     }
+}
 
-    /// If music is fading, or not.
-    pub fn get_fading() -> Fading {
-        let ret = unsafe { ffi::Mix_FadingMusic() as isize } as c_uint;
-        match ret {
-            ffi::MIX_FADING_OUT    => Fading::FadingOut,
-            ffi::MIX_FADING_IN     => Fading::FadingIn,
-            ffi::MIX_NO_FADING | _ => Fading::NoFading
+/// Where `MixerEvent`s are delivered, so the game loop can react to them on
+/// its own thread instead of inside a callback on the audio thread.
+#[derive(Debug, Clone)]
+pub enum EventSink {
+    /// Send the events through a `std::sync::mpsc` channel.
+    Sender(mpsc::Sender<MixerEvent>),
+    /// Push the events to the SDL event queue, as user events of this type,
+    /// e.g. from `EventSubsystem::register_event`. See `MixerEvent::from_sdl_event`.
+    #[cfg(feature = "native")]
+    Sdl(u32),
+}
+
+impl EventSink {
+    fn deliver(&self, event: MixerEvent) {
+        match *self {
+            // A disconnected receiver just isn't interested anymore.
+            EventSink::Sender(ref tx) => {
+                let _ = tx.send(event);
+            }
+            #[cfg(feature = "native")]
+            EventSink::Sdl(type_) => {
+                let (code, data1) = match event {
+                    MixerEvent::ChannelFinished(ch) => {
+                        (USER_EVENT_CHANNEL_FINISHED, ch as *mut c_void)
+                    }
+                    MixerEvent::MusicFinished => (USER_EVENT_MUSIC_FINISHED, ptr::null_mut()),
+                    MixerEvent::SynchroChanged(value) => {
+                        (USER_EVENT_SYNCHRO_CHANGED, value as *mut c_void)
+                    }
+                };
+                push_user_event(type_, code, data1);
+            }
         }
     }
 }
 
+/// Push a user event, `EventSubsystem::push_event` can't be reached from the audio thread.
+#[cfg(feature = "native")]
+fn push_user_event(type_: u32, code: i32, data1: *mut c_void) {
+    unsafe {
+        let mut raw: ffi::SDL_Event = mem::zeroed();
+        let user = raw.user();
+        (*user).type_ = type_;
+        (*user).code = code;
+        (*user).data1 = data1;
+        // A full event queue drops the event, there is nobody to report to.
+        ffi::SDL_PushEvent(&mut raw);
+    }
+}
+
+/// Deliver a `MixerEvent::ChannelFinished` to `sink` whenever channel playback
+/// is halted, in place of a `set_channel_finished` callback.
+pub fn set_channel_finished_events(sink: EventSink) {
+    set_channel_finished(Box::new(move |ch| sink.deliver(MixerEvent::ChannelFinished(ch.number()))));
+}
+
+/// Receive both channel and music finished events through a new channel,
+/// replacing any callbacks set with `set_channel_finished` and `Music::hook_finished`.
+pub fn finished_events() -> mpsc::Receiver<MixerEvent> {
+    let (tx, rx) = mpsc::channel();
+    set_channel_finished_events(EventSink::Sender(tx.clone()));
+    Music::hook_finished_events(EventSink::Sender(tx));
+    rx
+}
+
 // 4.6 Effects
 
-// TODO: Mix_RegisterEffect
-// TODO: Mix_UnregisterEffect
-// TODO: Mix_SetPostMix
+/// Locks a mutex shared with the audio thread, ignoring poisoning: panics in
+/// user callbacks are caught before they can unwind through a guard.
+fn lock_ignore_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Holds the audio lock of the backend, so the mixing callback can't run
+/// meanwhile.
+struct AudioLock(Arc<dyn Backend>);
+
+impl AudioLock {
+    fn new() -> AudioLock {
+        AudioLock::of(backend::current())
+    }
+
+    fn of(backend: Arc<dyn Backend>) -> AudioLock {
+        backend.lock_audio();
+        AudioLock(backend)
+    }
+}
+
+impl Drop for AudioLock {
+    fn drop(&mut self) {
+        self.0.unlock_audio();
+    }
+}
+
+/// A custom effect, see `Channel::register_effect`.
+pub type EffectFn = Box<dyn FnMut(&mut [u8]) + Send>;
+
+/// Called once a custom effect is unregistered.
+pub type EffectDoneFn = Box<dyn FnOnce() + Send>;
+
+/// A Rust effect attached to a channel.
+struct RegisteredEffect {
+    id: usize,
+    effect: EffectFn,
+    done: Option<EffectDoneFn>,
+}
+
+impl RegisteredEffect {
+    fn finish(self) {
+        if let Some(done) = self.done {
+            let _ = panic::catch_unwind(AssertUnwindSafe(done));
+        }
+    }
+}
+
+// All Rust effects of a channel share a single `Mix_RegisterEffect` entry,
+// since `Mix_UnregisterEffect` can only tell effects apart by function pointer.
+// A channel has an entry here exactly while the trampoline is registered.
+static EFFECTS: Mutex<BTreeMap<isize, Vec<RegisteredEffect>>> = Mutex::new(BTreeMap::new());
+static NEXT_EFFECT_ID: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn c_effect_callback(chan: c_int,
+                                stream: *const c_void,
+                                len: c_int,
+                                _udata: *const c_void) {
+    let buf = unsafe { slice::from_raw_parts_mut(stream as *mut u8, len as usize) };
+    let mut effects = lock_ignore_poison(&EFFECTS);
+    if let Some(list) = effects.get_mut(&(chan as isize)) {
+        for registered in list.iter_mut() {
+            let effect = &mut registered.effect;
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| effect(buf)));
+        }
+    }
+}
+
+extern "C" fn c_effect_done(chan: c_int, _udata: *const c_void) {
+    let removed = lock_ignore_poison(&EFFECTS).remove(&(chan as isize));
+    for registered in removed.into_iter().flat_map(|list| list.into_iter()) {
+        registered.finish();
+    }
+}
+
+/// An effect registered by `Channel::register_effect`.
+///
+/// Dropping the handle unregisters the effect and runs its done callback.
+#[derive(Debug)]
+pub struct EffectHandle<'a> {
+    channel: Channel<'a>,
+    id: usize,
+}
+
+impl<'a> EffectHandle<'a> {
+    /// The channel the effect is registered on.
+    pub fn channel(&self) -> Channel<'a> {
+        self.channel
+    }
+}
+
+impl<'a> Drop for EffectHandle<'a> {
+    fn drop(&mut self) {
+        let Channel(ch, _) = self.channel;
+        let removed = {
+            let _audio = AudioLock::new();
+            let (removed, now_empty) = {
+                let mut effects = lock_ignore_poison(&EFFECTS);
+                match effects.get_mut(&ch) {
+                    // Already removed by SDL_mixer, e.g. when the channel finished.
+                    None => (None, false),
+                    Some(list) => {
+                        let removed = list.iter()
+                                          .position(|e| e.id == self.id)
+                                          .map(|pos| list.remove(pos));
+                        (removed, list.is_empty())
+                    }
+                }
+            };
+            if now_empty {
+                // The done trampoline takes the now empty list out of `EFFECTS`.
+                backend::current().unregister_effect(ch, Some(c_effect_callback));
+            }
+            removed
+        };
+        if let Some(registered) = removed {
+            registered.finish();
+        }
+    }
+}
+
+/// The post-mix hook, see `set_post_mix`.
+type PostMixFn = Box<dyn FnMut(&mut [u8]) + Send>;
+
+static POST_MIX: Mutex<Option<(usize, PostMixFn)>> = Mutex::new(None);
+static NEXT_POST_MIX_ID: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn c_post_mix_callback(_udata: *mut c_void, stream: *mut u8, len: c_int) {
+    let buf = slice::from_raw_parts_mut(stream, len as usize);
+    if let Some((_, ref mut hook)) = *lock_ignore_poison(&POST_MIX) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(buf)));
+    }
+}
+
+/// A post-mix hook installed by `set_post_mix`, removed when dropped.
+#[derive(Debug)]
+pub struct PostMixHook {
+    id: usize,
+}
+
+impl Drop for PostMixHook {
+    fn drop(&mut self) {
+        let removed = {
+            let _audio = AudioLock::new();
+            let mut post_mix = lock_ignore_poison(&POST_MIX);
+            match *post_mix {
+                // Replaced by a later `set_post_mix` call.
+                Some((id, _)) if id != self.id => None,
+                _ => {
+                    unsafe { backend::current().set_post_mix(None, ptr::null_mut()) };
+                    post_mix.take()
+                }
+            }
+        };
+        // Free the hook outside of the audio lock.
+        drop(removed);
+    }
+}
+
+fn install_post_mix(hook: PostMixFn) -> PostMixHook {
+    let id = NEXT_POST_MIX_ID.fetch_add(1, Ordering::Relaxed);
+    let replaced = {
+        let _audio = AudioLock::new();
+        let replaced = lock_ignore_poison(&POST_MIX).replace((id, hook));
+        unsafe { backend::current().set_post_mix(Some(c_post_mix_callback), ptr::null_mut()) };
+        replaced
+    };
+    drop(replaced);
+    PostMixHook { id }
+}
+
+/// Installs a hook that sees, and may modify, the final mixed stream right
+/// before it is played, after all channels, music and effects were mixed.
+///
+/// The sample type `T` must match the format returned by `query_spec()`,
+/// e.g. `i16` for `AUDIO_S16SYS`. A later call replaces the previous hook.
+/// The hook runs on the audio thread and must not call mixer functions.
+pub fn set_post_mix<T, F>(mut f: F) -> Result<PostMixHook, MixerError>
+    where T: Sample,
+          F: FnMut(&mut [T]) + Send + 'static
+{
+    let format = query_spec()?.format;
+    if format != T::audio_format() {
+        return Err(MixerError::FormatMismatch(format));
+    }
+    let hook: PostMixFn = Box::new(move |buf: &mut [u8]| {
+        let samples = unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut T,
+                                      buf.len() / mem::size_of::<T>())
+        };
+        f(samples)
+    });
+    Ok(install_post_mix(hook))
+}
+
+/// Installs a post-mix hook like `set_post_mix`, called with a view of the
+/// stream that works for any host byte order format.
+pub fn set_post_mix_buffer<F>(mut f: F) -> Result<PostMixHook, MixerError>
+    where F: FnMut(&mut AudioBufferMut) + Send + 'static
+{
+    let spec = query_spec()?;
+    AudioBufferMut::new(&mut [], spec.format, spec.channels as usize)?;
+    let hook: PostMixFn = Box::new(move |buf: &mut [u8]| {
+        if let Ok(mut buf) = AudioBufferMut::new(buf, spec.format, spec.channels as usize) {
+            f(&mut buf);
+        }
+    });
+    Ok(install_post_mix(hook))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::Path;
+    use std::time::Duration;
+    use super::{check_quick_load_wav, set_soundfonts, AudioFormat, AudioSpec, MixerError};
+
+    fn spec(frequency: isize, format: AudioFormat, channels: isize) -> AudioSpec {
+        AudioSpec {
+            frequency,
+            format,
+            channels,
+        }
+    }
+
+    #[test]
+    fn spec_frame_arithmetic() {
+        let stereo = spec(44100, AudioFormat::S16LSB, 2);
+        assert_eq!(stereo.bytes_per_frame(), 4);
+        assert_eq!(spec(48000, AudioFormat::F32MSB, 6).bytes_per_frame(), 24);
+        assert_eq!(spec(8000, AudioFormat::U8, 1).bytes_per_frame(), 1);
+        assert_eq!(stereo.frames_in(Duration::from_millis(1500)), 66150);
+        assert_eq!(stereo.frames_in(Duration::new(0, 22_675)), 0);
+        assert_eq!(stereo.frames_in(Duration::new(0, 22_676)), 1);
+    }
+
+    #[test]
+    fn spec_durations() {
+        let stereo = spec(44100, AudioFormat::S16LSB, 2);
+        assert_eq!(stereo.duration_of(0), Duration::new(0, 0));
+        assert_eq!(stereo.duration_of(66150), Duration::from_millis(1500));
+        assert_eq!(stereo.duration_of(1), Duration::new(0, 22_675));
+        assert_eq!(stereo.duration_of(44101), Duration::new(1, 22_675));
+        let frames = stereo.frames_in(Duration::from_secs(3600));
+        assert_eq!(stereo.duration_of(frames), Duration::from_secs(3600));
+    }
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        let len = body.len() as u32;
+        chunk.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8,
+                                  (len >> 24) as u8]);
+        chunk.extend_from_slice(body);
+        chunk
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        body.extend_from_slice(&chunks.concat());
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn quick_load_wav_finds_the_data_chunk() {
+        let wav = riff(&[chunk(b"fmt ", &[0; 16]),
+                         chunk(b"LIST", &[0; 4]),
+                         chunk(b"data", &[0; 3])]);
+        assert!(check_quick_load_wav(&wav).is_ok());
+    }
+
+    #[test]
+    fn quick_load_wav_rejects_other_files() {
+        assert!(check_quick_load_wav(b"").is_err());
+        assert!(check_quick_load_wav(b"RIFF\0\0\0\0WAV").is_err());
+        assert!(check_quick_load_wav(b"RIFX\0\0\0\0WAVE").is_err());
+    }
+
+    #[test]
+    fn quick_load_wav_needs_a_data_chunk() {
+        assert!(check_quick_load_wav(&riff(&[])).is_err());
+        assert!(check_quick_load_wav(&riff(&[chunk(b"fmt ", &[0; 16])])).is_err());
+    }
+
+    #[test]
+    fn quick_load_wav_rejects_truncated_chunks() {
+        let mut wav = riff(&[chunk(b"fmt ", &[0; 16]), chunk(b"data", &[0; 8])]);
+        wav.truncate(wav.len() - 1);
+        assert!(check_quick_load_wav(&wav).is_err());
+        let mut wav = riff(&[chunk(b"fmt ", &[0; 16])]);
+        wav.extend_from_slice(b"data");
+        assert!(check_quick_load_wav(&wav).is_err());
+    }
+
+    #[test]
+    fn quick_load_wav_rejects_odd_sized_chunks_before_the_data() {
+        let mut odd = chunk(b"note", &[0; 3]);
+        odd.push(0);
+        let wav = riff(&[odd, chunk(b"data", &[0; 4])]);
+        match check_quick_load_wav(&wav) {
+            Err(MixerError::InvalidData(ref msg)) => assert!(msg.contains("odd size"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn soundfonts_must_be_files() {
+        let dir = env::temp_dir();
+        match set_soundfonts(&[&dir]) {
+            Err(MixerError::Load { ref path, ref reason }) => {
+                assert_eq!(path, &dir);
+                assert_eq!(reason, "not a file");
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(set_soundfonts(&[Path::new("/no/such/soundfont.sf2")]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn soundfonts_must_be_readable() {
+        use std::fs::{self, File};
+        use std::os::unix::fs::PermissionsExt;
+        use std::process;
+
+        let path = env::temp_dir().join(format!("sdl2_mixer-unreadable-{}.sf2", process::id()));
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root.
+        let result = if File::open(&path).is_ok() {
+            None
+        } else {
+            Some(set_soundfonts(&[&path]))
+        };
+        fs::remove_file(&path).unwrap();
+        match result {
+            None => {}
+            Some(Err(MixerError::Load { path: ref failed, .. })) => assert_eq!(failed, &path),
+            Some(other) => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! An `SDL_RWops` reading from any `std::io::Read + Seek`.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use libc::{c_int, c_void, size_t};
+
+use backend;
+use ffi::SDL_RWops;
+
+// SDL_RWOPS_UNKNOWN, SDL only looks at the callbacks of such streams.
+const RWOPS_UNKNOWN: u32 = 0;
+
+/// Mirrors the public part of `SDL_RWops`, followed by the reader.
+///
+/// SDL never touches the private `hidden` part of an unknown stream type,
+/// so the reader can take its place.
+#[repr(C)]
+struct ReaderRWops<R> {
+    size: extern "C" fn(*mut SDL_RWops) -> i64,
+    seek: extern "C" fn(*mut SDL_RWops, i64, c_int) -> i64,
+    read: extern "C" fn(*mut SDL_RWops, *mut c_void, size_t, size_t) -> size_t,
+    write: extern "C" fn(*mut SDL_RWops, *const c_void, size_t, size_t) -> size_t,
+    close: extern "C" fn(*mut SDL_RWops) -> c_int,
+    type_: u32,
+    reader: R,
+}
+
+fn set_error(msg: &str) {
+    backend::current().set_error(msg);
+}
+
+fn set_io_error(e: &io::Error) {
+    set_error(&format!("IO error: {}", e));
+}
+
+/// Run `f` on the reader behind `context`, turning an IO error or a panic
+/// into `on_error`.
+fn with_reader<R, T, F>(context: *mut SDL_RWops, on_error: T, f: F) -> T
+    where F: FnOnce(&mut R) -> io::Result<T>
+{
+    let rw = unsafe { &mut *(context as *mut ReaderRWops<R>) };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut rw.reader))) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_io_error(&e);
+            on_error
+        }
+        Err(_) => {
+            set_error("panic while reading");
+            on_error
+        }
+    }
+}
+
+extern "C" fn size<R: Seek>(context: *mut SDL_RWops) -> i64 {
+    with_reader(context, -1, |reader: &mut R| {
+        let pos = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(pos))?;
+        Ok(len as i64)
+    })
+}
+
+extern "C" fn seek<R: Seek>(context: *mut SDL_RWops, offset: i64, whence: c_int) -> i64 {
+    let pos = match whence {
+        0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => {
+            set_error("Unknown value for 'whence'");
+            return -1;
+        }
+    };
+    with_reader(context, -1, |reader: &mut R| reader.seek(pos).map(|pos| pos as i64))
+}
+
+extern "C" fn read<R: Read>(context: *mut SDL_RWops,
+                            ptr: *mut c_void,
+                            size: size_t,
+                            maxnum: size_t)
+                            -> size_t {
+    let total = match size.checked_mul(maxnum) {
+        Some(0) | None => return 0,
+        Some(total) => total,
+    };
+    let buf = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, total) };
+    // Like fread, fill as much as possible and report whole objects only.
+    with_reader(context, 0, |reader: &mut R| {
+        let mut filled = 0;
+        while filled < total {
+            match reader.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled / size)
+    })
+}
+
+extern "C" fn write(_context: *mut SDL_RWops,
+                    _ptr: *const c_void,
+                    _size: size_t,
+                    _num: size_t)
+                    -> size_t {
+    set_error("Can't write to a read-only stream");
+    0
+}
+
+extern "C" fn close<R>(context: *mut SDL_RWops) -> c_int {
+    let rw = unsafe { Box::from_raw(context as *mut ReaderRWops<R>) };
+    // A panicking destructor must not unwind into SDL. The stream is gone
+    // either way, and `RWops` would panic on an error anyway.
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(rw)));
+    0
+}
+
+/// An `SDL_RWops` owned by the crate, closed when dropped.
+pub struct Source<'a> {
+    raw: *mut SDL_RWops,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> Source<'a> {
+    /// Take ownership of `raw`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be a valid stream that nothing else closes.
+    #[cfg(feature = "native")]
+    pub unsafe fn from_raw(raw: *mut SDL_RWops) -> Source<'a> {
+        Source {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn raw(&self) -> *mut SDL_RWops {
+        self.raw
+    }
+
+    /// Give up ownership, to whatever closes the stream instead.
+    pub fn into_raw(self) -> *mut SDL_RWops {
+        let raw = self.raw;
+        mem::forget(self);
+        raw
+    }
+}
+
+impl<'a> Drop for Source<'a> {
+    fn drop(&mut self) {
+        unsafe { ((*self.raw).close)(self.raw) };
+    }
+}
+
+/// Wraps `reader` in an `SDL_RWops`, which drops it when closed.
+pub fn from_reader<'a, R: Read + Seek + 'a>(reader: R) -> Source<'a> {
+    let rw = Box::new(ReaderRWops {
+        size: size::<R>,
+        seek: seek::<R>,
+        read: read::<R>,
+        write,
+        close: close::<R>,
+        type_: RWOPS_UNKNOWN,
+        reader,
+    });
+    Source {
+        raw: Box::into_raw(rw) as *mut SDL_RWops,
+        _marker: PhantomData,
+    }
+}
+
+// Through `RWops`, as SDL sees the streams.
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::MutexGuard;
+    use libc::c_void;
+    use sdl2::rwops::RWops;
+    use backend;
+    use {lock_ignore_poison, TEST_LOCK};
+    use super::{ReaderRWops, RWOPS_UNKNOWN};
+
+    fn from_reader<'a, R: Read + Seek + 'a>(reader: R) -> RWops<'a> {
+        unsafe { RWops::from_ll(super::from_reader(reader).into_raw()) }
+    }
+
+    fn bytes() -> Cursor<Vec<u8>> {
+        Cursor::new((0..10).collect())
+    }
+
+    // Keeps fake mixers in other tests from switching the backend, which
+    // the errors are set through.
+    fn lock() -> MutexGuard<'static, ()> {
+        lock_ignore_poison(&TEST_LOCK)
+    }
+
+    fn error() -> String {
+        backend::current().get_error()
+    }
+
+    #[test]
+    fn mirrors_the_public_part_of_sdl_rwops() {
+        let rw = from_reader(bytes());
+        unsafe {
+            let raw = rw.raw();
+            let mirror = raw as *mut ReaderRWops<Cursor<Vec<u8>>>;
+            assert_eq!(&(*raw).close as *const _ as usize,
+                       &(*mirror).close as *const _ as usize);
+            assert_eq!(&(*raw).type_ as *const _ as usize,
+                       &(*mirror).type_ as *const _ as usize);
+            assert_eq!((*raw).type_, RWOPS_UNKNOWN);
+        }
+    }
+
+    #[test]
+    fn size_keeps_the_position() {
+        let mut rw = from_reader(bytes());
+        assert_eq!(rw.seek(SeekFrom::Start(3)).unwrap(), 3);
+        assert_eq!(rw.len(), Some(10));
+        assert_eq!(rw.stream_position().unwrap(), 3);
+        assert_eq!(rw.seek(SeekFrom::End(-2)).unwrap(), 8);
+        let mut buf = [0; 4];
+        assert_eq!(rw.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], &[8, 9]);
+    }
+
+    #[test]
+    fn reads_whole_objects_only() {
+        let rw = from_reader(bytes());
+        let mut buf = [0u8; 12];
+        unsafe {
+            let raw = rw.raw();
+            let ptr = buf.as_mut_ptr() as *mut c_void;
+            assert_eq!(((*raw).read)(raw, ptr, 4, 3), 2);
+            assert_eq!(((*raw).read)(raw, ptr, 4, 3), 0);
+            assert_eq!(((*raw).read)(raw, ptr, 0, 3), 0);
+            assert_eq!(((*raw).read)(raw, ptr, usize::MAX, 2), 0);
+        }
+        assert_eq!(&buf[..10], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn seek_errors() {
+        let _lock = lock();
+        let rw = from_reader(bytes());
+        unsafe {
+            let raw = rw.raw();
+            assert_eq!(((*raw).seek)(raw, 0, 3), -1);
+            assert_eq!(error(), "Unknown value for 'whence'");
+            assert_eq!(((*raw).seek)(raw, -1, 1), -1);
+            assert!(error().starts_with("IO error: "));
+        }
+    }
+
+    #[test]
+    fn is_read_only() {
+        let _lock = lock();
+        let mut rw = from_reader(bytes());
+        assert_eq!(rw.write(&[1, 2, 3]).unwrap(), 0);
+        assert_eq!(error(), "Can't write to a read-only stream");
+    }
+
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "broken"))
+        }
+    }
+
+    impl Seek for Failing {
+        fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+            panic!("unseekable");
+        }
+    }
+
+    #[test]
+    fn reader_errors_and_panics_become_sdl_errors() {
+        let _lock = lock();
+        let mut rw = from_reader(Failing);
+        let mut buf = [0; 4];
+        assert_eq!(rw.read(&mut buf).unwrap(), 0);
+        assert_eq!(error(), "IO error: broken");
+        assert_eq!(rw.len(), None);
+        assert_eq!(error(), "panic while reading");
+    }
+
+    struct Dropped(Arc<AtomicBool>);
+
+    impl Read for Dropped {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Seek for Dropped {
+        fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn closing_drops_the_reader() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let rw = from_reader(Dropped(dropped.clone()));
+        assert!(!dropped.load(Ordering::SeqCst));
+        drop(rw);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
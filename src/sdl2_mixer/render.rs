@@ -0,0 +1,428 @@
+//! Render a scripted mix to a WAV file, faster than real time.
+//!
+//! The mix is played by SDL_mixer itself on SDL's `disk` audio driver, so
+//! it needs no sound card. The timeline runs on the calling thread while the
+//! device is paused between two mixed buffers, so commands are late by at
+//! most `OfflineRenderer::chunk_size` frames.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use std::time::Duration;
+//! use sdl2_mixer::render::{Command, OfflineRenderer, Timeline};
+//!
+//! let renderer = OfflineRenderer::default();
+//! renderer.render(Path::new("trailer.wav"), Duration::from_secs(10), |device| {
+//!     let boom = device.load_chunk(Path::new("boom.wav"))?;
+//!     let theme = device.load_music(Path::new("theme.ogg"))?;
+//!     Ok(Timeline::new()
+//!         .at(Duration::from_secs(0), Command::PlayMusic { music: theme, loops: -1 })
+//!         .at(Duration::from_millis(1200),
+//!             Command::Play { channel: device.all_channels(), chunk: boom, loops: 0, limit: None })
+//!         .at(Duration::from_secs(5), Command::FadeOutMusic(Duration::from_secs(5))))
+//! }).unwrap();
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use driver::Headless;
+use wav::{save_error, to_wav_samples, write_header, MAX_DATA_LEN};
+use {AudioDevice, AudioSpec, Channel, Chunk, Fading, Group, MixerError, Music, DEFAULT_CHANNELS,
+     DEFAULT_FORMAT, DEFAULT_FREQUENCY, OPEN_DEVICES};
+
+/// Something to do at a point of a `Timeline`, mirroring the `Channel`,
+/// `Group` and `MusicPlayer` functions of the same name.
+///
+/// Durations are converted to frames of the rendered mix, rather than timed
+/// with the wall clock as SDL_mixer does for channels.
+#[derive(Clone)]
+pub enum Command<'a> {
+    /// Play `chunk`, on the first free channel for `AudioDevice::all_channels`, for at
+    /// most `limit` if given.
+    Play {
+        channel: Channel<'a>,
+        chunk: Chunk,
+        loops: isize,
+        limit: Option<Duration>,
+    },
+    /// Play `chunk` like `Play`, fading in over `fade`.
+    FadeIn {
+        channel: Channel<'a>,
+        chunk: Chunk,
+        loops: isize,
+        fade: Duration,
+        limit: Option<Duration>,
+    },
+    Halt(Channel<'a>),
+    /// Fade out over the duration, then halt.
+    FadeOut(Channel<'a>, Duration),
+    Pause(Channel<'a>),
+    Resume(Channel<'a>),
+    SetVolume(Channel<'a>, isize),
+    SetPanning(Channel<'a>, u8, u8),
+    SetPosition(Channel<'a>, i16, u8),
+    SetDistance(Channel<'a>, u8),
+    /// Add the channel to the group.
+    AddToGroup(Group<'a>, Channel<'a>),
+    HaltGroup(Group<'a>),
+    FadeOutGroup(Group<'a>, Duration),
+    PlayMusic { music: Music, loops: isize },
+    FadeInMusic {
+        music: Music,
+        loops: isize,
+        fade: Duration,
+    },
+    HaltMusic,
+    FadeOutMusic(Duration),
+    PauseMusic,
+    ResumeMusic,
+    SetMusicVolume(isize),
+}
+
+impl<'a> Command<'a> {
+    /// Whether the command starts music, which SDL_mixer holds back until
+    /// music fading out has finished.
+    fn starts_music(&self) -> bool {
+        matches!(*self, Command::PlayMusic { .. } | Command::FadeInMusic { .. })
+    }
+}
+
+/// Commands to run at given times from the start of the mix.
+#[derive(Clone, Default)]
+pub struct Timeline<'a> {
+    events: Vec<(Duration, Command<'a>)>,
+}
+
+impl<'a> Timeline<'a> {
+    pub fn new() -> Timeline<'a> {
+        Timeline { events: Vec::new() }
+    }
+
+    /// Add a command to run at `time`, after those already added for the same time.
+    pub fn at(mut self, time: Duration, command: Command<'a>) -> Timeline<'a> {
+        self.push(time, command);
+        self
+    }
+
+    /// Add a command to run at `time`, after those already added for the same time.
+    pub fn push(&mut self, time: Duration, command: Command<'a>) {
+        self.events.push((time, command));
+    }
+}
+
+fn to_ms(time: Duration) -> isize {
+    (time.as_secs() * 1000 + time.subsec_nanos() as u64 / 1_000_000) as isize
+}
+
+/// A channel volume ramp, as SDL_mixer does for fading channels.
+struct Ramp {
+    start: u64,
+    len: u64,
+    from: isize,
+    to: isize,
+    // The volume to restore once the channel is halted.
+    reset: isize,
+}
+
+/// The state of the mix, in frames since it started.
+struct Mix<'a> {
+    device: &'a AudioDevice,
+    spec: AudioSpec,
+    channels: isize,
+    frame: u64,
+    ramps: BTreeMap<isize, Ramp>,
+    expires: BTreeMap<isize, u64>,
+    groups: BTreeMap<isize, isize>,
+}
+
+impl<'a> Mix<'a> {
+    /// The channels `channel` stands for, all of them for `all_channels`.
+    fn each(&self, Channel(ch, _): Channel) -> Vec<isize> {
+        if ch == -1 {
+            (0..self.channels).collect()
+        } else {
+            vec![ch]
+        }
+    }
+
+    fn in_group(&self, Group(tag, _): Group) -> Vec<isize> {
+        (0..self.channels)
+            .filter(|ch| tag == -1 || self.groups.get(ch) == Some(&tag))
+            .collect()
+    }
+
+    /// Forget the fade and time limit of `ch`, restoring its volume.
+    fn reset(&mut self, ch: isize) {
+        self.expires.remove(&ch);
+        if let Some(ramp) = self.ramps.remove(&ch) {
+            self.device.channel(ch).set_volume(ramp.reset);
+        }
+    }
+
+    fn halt(&mut self, ch: isize) {
+        self.reset(ch);
+        self.device.channel(ch).halt();
+    }
+
+    fn fade_out(&mut self, ch: isize, fade: Duration) {
+        let channel = self.device.channel(ch);
+        if !channel.is_playing() || self.ramps.contains_key(&ch) {
+            return;
+        }
+        let volume = channel.get_volume();
+        self.ramps.insert(ch,
+                          Ramp {
+                              start: self.frame,
+                              len: self.spec.frames_in(fade),
+                              from: volume,
+                              to: 0,
+                              reset: volume,
+                          });
+    }
+
+    fn play(&mut self,
+            channel: Channel<'a>,
+            chunk: &Chunk,
+            loops: isize,
+            fade: Option<Duration>,
+            limit: Option<Duration>)
+            -> Result<(), MixerError> {
+        if channel.number() != -1 {
+            self.reset(channel.number());
+        }
+        let ch = channel.play(chunk, loops)?.number();
+        if let Some(limit) = limit {
+            self.expires.insert(ch, self.frame + self.spec.frames_in(limit));
+        }
+        if let Some(fade) = fade {
+            let volume = self.device.channel(ch).get_volume();
+            self.device.channel(ch).set_volume(0);
+            self.ramps.insert(ch,
+                              Ramp {
+                                  start: self.frame,
+                                  len: self.spec.frames_in(fade),
+                                  from: 0,
+                                  to: volume,
+                                  reset: volume,
+                              });
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, command: &Command<'a>) -> Result<(), MixerError> {
+        match *command {
+            Command::Play { channel, ref chunk, loops, limit } => {
+                self.play(channel, chunk, loops, None, limit)?
+            }
+            Command::FadeIn { channel, ref chunk, loops, fade, limit } => {
+                self.play(channel, chunk, loops, Some(fade), limit)?
+            }
+            Command::Halt(channel) => {
+                for ch in self.each(channel) {
+                    self.halt(ch);
+                }
+            }
+            Command::FadeOut(channel, fade) => {
+                for ch in self.each(channel) {
+                    self.fade_out(ch, fade);
+                }
+            }
+            Command::Pause(channel) => channel.pause(),
+            Command::Resume(channel) => channel.resume(),
+            Command::SetVolume(channel, volume) => {
+                channel.set_volume(volume);
+            }
+            Command::SetPanning(channel, left, right) => channel.set_panning(left, right)?,
+            Command::SetPosition(channel, angle, distance) => {
+                channel.set_position(angle, distance)?
+            }
+            Command::SetDistance(channel, distance) => channel.set_distance(distance)?,
+            Command::AddToGroup(group, channel) => {
+                if group.add_channel(channel) {
+                    self.groups.insert(channel.number(), group.0);
+                } else {
+                    return Err(MixerError::last());
+                }
+            }
+            Command::HaltGroup(group) => {
+                for ch in self.in_group(group) {
+                    self.halt(ch);
+                }
+            }
+            Command::FadeOutGroup(group, fade) => {
+                for ch in self.in_group(group) {
+                    self.fade_out(ch, fade);
+                }
+            }
+            Command::PlayMusic { ref music, loops } => self.device.music().play(music, loops)?,
+            Command::FadeInMusic { ref music, loops, fade } => {
+                self.device.music().fade_in(music, loops, to_ms(fade))?
+            }
+            Command::HaltMusic => self.device.music().halt(),
+            Command::FadeOutMusic(fade) => self.device.music().fade_out(to_ms(fade))?,
+            Command::PauseMusic => self.device.music().pause(),
+            Command::ResumeMusic => self.device.music().resume(),
+            Command::SetMusicVolume(volume) => self.device.music().set_volume(volume),
+        }
+        Ok(())
+    }
+
+    /// Apply fades and time limits at the current frame.
+    fn update(&mut self) {
+        let frame = self.frame;
+        let expired: Vec<isize> = self.expires
+                                      .iter()
+                                      .filter(|&(_, &end)| end <= frame)
+                                      .map(|(&ch, _)| ch)
+                                      .collect();
+        for ch in expired {
+            self.halt(ch);
+        }
+        let mut faded = Vec::new();
+        for (&ch, ramp) in &self.ramps {
+            if !self.device.channel(ch).is_playing() {
+                faded.push((ch, false));
+            } else if frame >= ramp.start + ramp.len {
+                faded.push((ch, ramp.to == 0));
+            } else {
+                let done = (frame - ramp.start) as f64 / ramp.len as f64;
+                let volume = ramp.from as f64 + (ramp.to - ramp.from) as f64 * done;
+                self.device.channel(ch).set_volume(volume as isize);
+            }
+        }
+        for (ch, halt) in faded {
+            if halt {
+                self.halt(ch);
+            } else {
+                self.reset(ch);
+            }
+        }
+    }
+}
+
+/// Renders `Timeline`s to WAV files, see the module documentation.
+#[derive(Debug, Copy, Clone)]
+pub struct OfflineRenderer {
+    /// The format of the mix and of the WAV file.
+    pub spec: AudioSpec,
+    /// Frames mixed at once, which commands are aligned to.
+    pub chunk_size: isize,
+    /// Number of channels to mix, as given to `allocate_channels`.
+    pub mixing_channels: isize,
+}
+
+impl Default for OfflineRenderer {
+    fn default() -> OfflineRenderer {
+        OfflineRenderer {
+            spec: AudioSpec {
+                frequency: DEFAULT_FREQUENCY,
+                format: DEFAULT_FORMAT,
+                channels: DEFAULT_CHANNELS,
+            },
+            chunk_size: 256,
+            mixing_channels: 8,
+        }
+    }
+}
+
+impl OfflineRenderer {
+    /// Render `length` of the mix to a WAV file at `path`.
+    ///
+    /// `script` builds the timeline, loading its samples for the offline
+    /// device. The renderer must own SDL audio: it fails if SDL audio is
+    /// already initialized, and quits it once done. SDL reads its driver from
+    /// the environment, which is changed while the device opens, so no other
+    /// thread may use the environment then.
+    pub fn render<F>(&self, path: &Path, length: Duration, script: F) -> Result<(), MixerError>
+        where F: for<'a> FnOnce(&'a AudioDevice) -> Result<Timeline<'a>, MixerError>
+    {
+        if OPEN_DEVICES.load(Ordering::SeqCst) != 0 {
+            return Err(MixerError::Sdl("Close the audio device before rendering offline"
+                                           .to_owned()));
+        }
+        let headless = Headless::open("disk", self.spec, self.chunk_size)?;
+        let spec = headless.spec();
+        let total = spec.frames_in(length);
+        if total * spec.bytes_per_frame() as u64 > MAX_DATA_LEN {
+            return Err(MixerError::Save {
+                path: path.to_owned(),
+                reason: "too long for a WAV file".to_owned(),
+            });
+        }
+        headless.device().allocate_channels(self.mixing_channels);
+        let timeline = script(headless.device())?;
+        let mut events: Vec<_> = timeline.events
+                                         .into_iter()
+                                         .map(|(time, command)| (spec.frames_in(time), command))
+                                         .collect();
+        // Stable, so commands for the same time keep their order.
+        events.sort_by_key(|&(frame, _)| frame);
+        let pending: VecDeque<_> = events.into();
+
+        let mut out = BufWriter::new(File::create(path).map_err(|e| save_error(path, e))?);
+        let written = self.write_mix(&mut out, path, &headless, total, pending);
+        drop(headless);
+        let written = written.and_then(|()| {
+            let mut finish = || -> io::Result<()> {
+                out.seek(SeekFrom::Start(0))?;
+                write_header(&mut out, &spec, (total * spec.bytes_per_frame() as u64) as u32)?;
+                out.flush()
+            };
+            finish().map_err(|e| save_error(path, e))
+        });
+        if written.is_err() {
+            // Its header still claims no audio.
+            drop(out);
+            let _ = fs::remove_file(path);
+        }
+        written
+    }
+
+    // Write the header and `total` frames of the mix, running `pending` on time.
+    fn write_mix(&self,
+                 out: &mut BufWriter<File>,
+                 path: &Path,
+                 headless: &Headless,
+                 total: u64,
+                 mut pending: VecDeque<(u64, Command)>)
+                 -> Result<(), MixerError> {
+        let spec = headless.spec();
+        write_header(out, &spec, 0).map_err(|e| save_error(path, e))?;
+
+        let mut mix = Mix {
+            device: headless.device(),
+            spec,
+            channels: self.mixing_channels,
+            frame: 0,
+            ramps: BTreeMap::new(),
+            expires: BTreeMap::new(),
+            groups: BTreeMap::new(),
+        };
+        let frame_size = spec.bytes_per_frame();
+        while mix.frame < total {
+            while let Some(&(frame, ref command)) = pending.front() {
+                // Starting music waits for a fade out in SDL_mixer, which
+                // can't finish while the device is paused.
+                if frame > mix.frame ||
+                   command.starts_music() && mix.device.music().get_fading() == Fading::FadingOut {
+                    break;
+                }
+                mix.run(command)?;
+                pending.pop_front();
+            }
+            mix.update();
+            headless.advance_frames(1)?;
+            let mut data = headless.take_output();
+            let frames = ((data.len() / frame_size) as u64).min(total - mix.frame);
+            data.truncate(frames as usize * frame_size);
+            to_wav_samples(&mut data, spec.format);
+            out.write_all(&data).map_err(|e| save_error(path, e))?;
+            mix.frame += frames;
+        }
+        Ok(())
+    }
+}
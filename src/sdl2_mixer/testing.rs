@@ -0,0 +1,279 @@
+//! A headless mixer for tests, enabled by the `testing` feature.
+//!
+//! `Harness` opens the mixer on SDL's `disk` or `dummy` audio driver, which
+//! need no sound card, and only mixes when told to, so tests can step
+//! through the mixed audio and check what was played.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use sdl2_mixer::testing::{Driver, Harness};
+//!
+//! let harness = Harness::open(Driver::Disk).unwrap();
+//! let beep = harness.tone(440.0, Duration::from_millis(100)).unwrap();
+//! let channel = harness.device().all_channels().play(&beep, 0).unwrap();
+//! harness.advance(Duration::from_millis(50));
+//! harness.assert_playing(channel);
+//! harness.advance(Duration::from_millis(100));
+//! harness.assert_stopped(channel);
+//! ```
+
+use std::f32::consts::PI;
+use std::sync::atomic::Ordering;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use driver::Headless;
+use wav::write_header;
+use {lock_ignore_poison, AudioFormat, AudioDevice, AudioSpec, Channel, Chunk, Fading, MixerError,
+     Music, DEFAULT_CHANNELS, DEFAULT_FORMAT, DEFAULT_FREQUENCY, OPEN_DEVICES, TEST_LOCK};
+
+/// The headless SDL audio driver to mix with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Driver {
+    /// Mixes as fast as asked to, writing to nowhere.
+    Disk,
+    /// Mixes in real time, discarding the output.
+    Dummy,
+}
+
+impl Driver {
+    fn name(self) -> &'static str {
+        match self {
+            Driver::Disk => "disk",
+            Driver::Dummy => "dummy",
+        }
+    }
+}
+
+/// The mixer opened on a headless driver, see the module documentation.
+///
+/// `advance` mixes as fast as it can, which is all the time that chunks and
+/// music playing, music fades and effects see. Channel fades and timed
+/// playback follow the wall clock in SDL_mixer instead, so wait for them with
+/// `advance_until`, which mixes in real time.
+///
+/// Nothing is mixed between two `advance` calls, and SDL_mixer waits for
+/// music fading out to finish before starting other music: advance past the
+/// fade, or halt the music, before playing more.
+pub struct Harness {
+    headless: Headless,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    /// Open the mixer on `driver` in the default format, with 8 channels.
+    pub fn open(driver: Driver) -> Result<Harness, MixerError> {
+        let spec = AudioSpec {
+            frequency: DEFAULT_FREQUENCY,
+            format: DEFAULT_FORMAT,
+            channels: DEFAULT_CHANNELS,
+        };
+        Harness::open_spec(driver, spec, 512)
+    }
+
+    /// Open the mixer on `driver`, mixing `chunk_size` frames at a time.
+    ///
+    /// Waits for harnesses and fake mixers open on other threads to be
+    /// dropped. Fails if the audio device was opened otherwise, or if SDL
+    /// audio is initialized: the harness owns it, and changes the environment
+    /// while opening the device, so no other thread may use it then.
+    pub fn open_spec(driver: Driver,
+                     spec: AudioSpec,
+                     chunk_size: isize)
+                     -> Result<Harness, MixerError> {
+        let lock = lock_ignore_poison(&TEST_LOCK);
+        if OPEN_DEVICES.load(Ordering::SeqCst) != 0 {
+            return Err(MixerError::Sdl("The audio device is already open".to_owned()));
+        }
+        Ok(Harness {
+            headless: Headless::open(driver.name(), spec, chunk_size)?,
+            _lock: lock,
+        })
+    }
+
+    /// The opened device.
+    pub fn device(&self) -> &AudioDevice {
+        self.headless.device()
+    }
+
+    /// The format the device mixes in.
+    pub fn spec(&self) -> AudioSpec {
+        self.headless.spec()
+    }
+
+    /// The number of frames mixed so far.
+    pub fn frames_mixed(&self) -> u64 {
+        self.headless.frames_mixed()
+    }
+
+    /// The time mixed so far.
+    pub fn elapsed(&self) -> Duration {
+        self.spec().duration_of(self.frames_mixed())
+    }
+
+    /// Mix at least `frames` more frames, rounded up to whole chunks.
+    ///
+    /// # Panics
+    ///
+    /// If the driver stops mixing.
+    pub fn advance_frames(&self, frames: u64) {
+        if let Err(e) = self.headless.advance_frames(frames) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Mix `time` more, rounded up to whole chunks.
+    ///
+    /// # Panics
+    ///
+    /// If the driver stops mixing.
+    pub fn advance(&self, time: Duration) {
+        self.advance_frames(self.spec().frames_in(time));
+    }
+
+    /// Mix a chunk at a time, no faster than it plays, until `done` returns
+    /// true, and return how long that took by the wall clock.
+    ///
+    /// This is for what SDL_mixer times by the wall clock, whose timing on a
+    /// busy machine only has a lower bound.
+    ///
+    /// # Panics
+    ///
+    /// If `done` still returns false after `timeout`, or the driver stops
+    /// mixing.
+    pub fn advance_until<F>(&self, timeout: Duration, mut done: F) -> Duration
+        where F: FnMut() -> bool
+    {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < timeout,
+                    "still waiting after {:?}, {:?} mixed",
+                    timeout,
+                    self.elapsed());
+            let mixed = self.elapsed();
+            self.advance_frames(1);
+            thread::sleep(self.elapsed() - mixed);
+        }
+        start.elapsed()
+    }
+
+    /// The mixed output since the last call, in the format of the device.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.headless.take_output()
+    }
+
+    /// The mixed output since the last call, as a chunk, e.g. to look at its
+    /// `samples` or to `save_wav` it.
+    pub fn take_output_chunk(&self) -> Result<Chunk, MixerError> {
+        Chunk::from_raw_samples(self.take_output())
+    }
+
+    /// A mono sine wave at `hz` and half the full volume, converted to the
+    /// format of the device.
+    pub fn tone(&self, hz: f32, length: Duration) -> Result<Chunk, MixerError> {
+        let samples = sine(hz, self.spec().frequency, self.spec().frames_in(length));
+        Chunk::from_samples_f32(&samples, 1, self.spec().frequency)
+    }
+
+    /// Music playing a tone like `tone`, from an in-memory WAV file.
+    pub fn tone_music(&self, hz: f32, length: Duration) -> Result<Music, MixerError> {
+        Music::from_bytes(self.tone_wav(hz, length))
+    }
+
+    /// A WAV file of a tone like `tone`, in 16-bit samples at the frequency
+    /// of the device.
+    pub fn tone_wav(&self, hz: f32, length: Duration) -> Vec<u8> {
+        let spec = AudioSpec {
+            frequency: self.spec().frequency,
+            format: AudioFormat::S16LSB,
+            channels: 1,
+        };
+        let samples = sine(hz, spec.frequency, spec.frames_in(length));
+        let mut wav = Vec::with_capacity(44 + samples.len() * 2);
+        write_header(&mut wav, &spec, (samples.len() * 2) as u32).unwrap();
+        for sample in samples {
+            wav.extend_from_slice(&((sample * 32767.0) as i16).to_le_bytes());
+        }
+        wav
+    }
+
+    /// # Panics
+    ///
+    /// If `channel` isn't playing, or is paused.
+    pub fn assert_playing(&self, channel: Channel) {
+        assert!(channel.is_playing() && !channel.is_paused(),
+                "{:?} is not playing after {:?}",
+                channel,
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If `channel` isn't paused.
+    pub fn assert_paused(&self, channel: Channel) {
+        assert!(channel.is_paused(),
+                "{:?} is not paused after {:?}",
+                channel,
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If `channel` is still playing.
+    pub fn assert_stopped(&self, channel: Channel) {
+        assert!(!channel.is_playing(),
+                "{:?} is still playing after {:?}",
+                channel,
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If `channel` isn't fading as given.
+    pub fn assert_fading(&self, channel: Channel, fading: Fading) {
+        let actual = channel.get_fading();
+        assert!(actual == fading,
+                "{:?} is {:?} rather than {:?} after {:?}",
+                channel,
+                actual,
+                fading,
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If no music is playing, or it is paused.
+    pub fn assert_music_playing(&self) {
+        assert!(self.device().music().is_playing() && !self.device().music().is_paused(),
+                "music is not playing after {:?}",
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If music is still playing.
+    pub fn assert_music_stopped(&self) {
+        assert!(!self.device().music().is_playing(),
+                "music is still playing after {:?}",
+                self.elapsed());
+    }
+
+    /// # Panics
+    ///
+    /// If the music isn't fading as given.
+    pub fn assert_music_fading(&self, fading: Fading) {
+        let actual = self.device().music().get_fading();
+        assert!(actual == fading,
+                "music is {:?} rather than {:?} after {:?}",
+                actual,
+                fading,
+                self.elapsed());
+    }
+}
+
+fn sine(hz: f32, rate: isize, frames: u64) -> Vec<f32> {
+    (0..frames)
+        .map(|i| 0.5 * (2.0 * PI * hz * i as f32 / rate as f32).sin())
+        .collect()
+}
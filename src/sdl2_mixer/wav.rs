@@ -0,0 +1,334 @@
+//! Writing chunks and the mixer output to RIFF/WAV files.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use {install_post_mix, query_spec, AudioFormat, AudioSpec, Chunk, MixerError, PostMixFn,
+     PostMixHook};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+pub(crate) fn save_error(path: &Path, e: io::Error) -> MixerError {
+    MixerError::Save {
+        path: path.to_owned(),
+        reason: e.to_string(),
+    }
+}
+
+/// The format samples of `format` are stored in, as WAV only has unsigned
+/// 8-bit and signed little-endian wider samples.
+fn wav_format(format: AudioFormat) -> AudioFormat {
+    match format {
+        AudioFormat::U8 | AudioFormat::S8 => AudioFormat::U8,
+        AudioFormat::U16LSB | AudioFormat::U16MSB | AudioFormat::S16LSB |
+        AudioFormat::S16MSB => AudioFormat::S16LSB,
+        AudioFormat::S32LSB | AudioFormat::S32MSB => AudioFormat::S32LSB,
+        AudioFormat::F32LSB | AudioFormat::F32MSB => AudioFormat::F32LSB,
+    }
+}
+
+/// Convert samples of `format` in place to `wav_format(format)`.
+pub(crate) fn to_wav_samples(data: &mut [u8], format: AudioFormat) {
+    let size = format.bytes_per_sample();
+    for sample in data.chunks_exact_mut(size) {
+        if format.is_big_endian() {
+            sample.reverse();
+        }
+        // Flip the sign bit, now in the last byte, of unsigned 16-bit and
+        // signed 8-bit samples.
+        if format.is_signed() == (size == 1) {
+            sample[size - 1] ^= 0x80;
+        }
+    }
+}
+
+/// Write the header of a WAV file of `spec` with `data_len` bytes of
+/// samples. Its length only depends on the format, so it can be rewritten
+/// once the samples are all written.
+///
+/// Float files aren't PCM, so they need the extended `fmt ` chunk and a
+/// `fact` chunk with the number of frames.
+pub(crate) fn write_header<W: Write>(out: &mut W,
+                                     spec: &AudioSpec,
+                                     data_len: u32)
+                                     -> io::Result<()> {
+    let format = wav_format(spec.format);
+    let channels = spec.channels as u16;
+    let block_align = channels * format.bytes_per_sample() as u16;
+    let float = format.is_float();
+    let (tag, fmt_len, riff_len) = if float {
+        (WAVE_FORMAT_IEEE_FLOAT, 18u32, 50 + data_len)
+    } else {
+        (WAVE_FORMAT_PCM, 16u32, 36 + data_len)
+    };
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_len.to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&fmt_len.to_le_bytes())?;
+    out.write_all(&tag.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&(spec.frequency as u32).to_le_bytes())?;
+    out.write_all(&(spec.frequency as u32 * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&(format.bits() as u16).to_le_bytes())?;
+    if float {
+        // No extra format bytes.
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(b"fact")?;
+        out.write_all(&4u32.to_le_bytes())?;
+        out.write_all(&(data_len / block_align as u32).to_le_bytes())?;
+    }
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())
+}
+
+// The largest data chunk a RIFF file can describe, with the longer header
+// of float files.
+pub(crate) const MAX_DATA_LEN: u64 = u32::MAX as u64 - 50;
+
+impl Chunk {
+    /// Write the sample to a WAV file, in the format of the opened audio device.
+    pub fn save_wav(&self, path: &Path) -> Result<(), MixerError> {
+        let spec = query_spec()?;
+        let mut data = self.bytes().to_vec();
+        if data.len() as u64 > MAX_DATA_LEN {
+            return Err(MixerError::Save {
+                path: path.to_owned(),
+                reason: "too large for a WAV file".to_owned(),
+            });
+        }
+        to_wav_samples(&mut data, spec.format);
+        let write = || {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_header(&mut out, &spec, data.len() as u32)?;
+            out.write_all(&data)?;
+            out.flush()
+        };
+        write().map_err(|e| save_error(path, e))
+    }
+}
+
+/// Records everything the mixer plays to a WAV file, in the format of the
+/// opened audio device, until it is finished or dropped.
+///
+/// The recorder takes the post-mix hook, so `set_post_mix` stops it. The
+/// file is written on a separate thread, the audio thread only copies the
+/// samples into one of a few preallocated blocks. When the writer falls
+/// behind and none is free, the audio of that callback is left out of the
+/// file and counted by `dropped_blocks`.
+#[derive(Debug)]
+pub struct WavRecorder {
+    path: PathBuf,
+    hook: Option<PostMixHook>,
+    writer: Option<thread::JoinHandle<io::Result<u64>>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+// The number of blocks handed between the audio thread and the writer, and
+// their initial size, enough for most callbacks. A block only grows for a
+// larger one.
+const BLOCKS: usize = 16;
+const BLOCK_CAPACITY: usize = 32 * 1024;
+
+fn write_stream(file: File,
+                spec: AudioSpec,
+                full: Receiver<Vec<u8>>,
+                empty: SyncSender<Vec<u8>>)
+                -> io::Result<u64> {
+    let mut out = BufWriter::new(file);
+    write_header(&mut out, &spec, 0)?;
+    let mut len: u64 = 0;
+    for data in full {
+        if len + data.len() as u64 > MAX_DATA_LEN {
+            break;
+        }
+        out.write_all(&data)?;
+        len += data.len() as u64;
+        // There is room for every block, and once the hook hangs up the
+        // block isn't needed.
+        let _ = empty.try_send(data);
+    }
+    out.seek(SeekFrom::Start(0))?;
+    write_header(&mut out, &spec, len as u32)?;
+    out.flush()?;
+    Ok(len)
+}
+
+impl WavRecorder {
+    /// Start recording to a new file at `path`.
+    pub fn start(path: &Path) -> Result<WavRecorder, MixerError> {
+        let spec = query_spec()?;
+        // Created here, so a bad path fails now rather than in `finish`.
+        let file = File::create(path).map_err(|e| save_error(path, e))?;
+        // Blocks go round from `empty` to the hook, to `full` and the
+        // writer, and back, so neither channel can fill up.
+        let (full_tx, full_rx) = mpsc::sync_channel(BLOCKS);
+        let (empty_tx, empty_rx) = mpsc::sync_channel(BLOCKS);
+        for _ in 0..BLOCKS {
+            empty_tx.send(Vec::with_capacity(BLOCK_CAPACITY)).unwrap();
+        }
+        let writer = thread::Builder::new()
+                         .name("WavRecorder".to_owned())
+                         .spawn(move || write_stream(file, spec, full_rx, empty_tx))
+                         .map_err(|e| save_error(path, e))?;
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let hook: PostMixFn = {
+            let dropped = dropped.clone();
+            Box::new(move |stream: &mut [u8]| {
+                let sent = match empty_rx.try_recv() {
+                    Ok(mut data) => {
+                        data.clear();
+                        data.extend_from_slice(stream);
+                        to_wav_samples(&mut data, spec.format);
+                        // The writer only hangs up after failing, which
+                        // `finish` reports, or once the file is full.
+                        full_tx.try_send(data).is_ok()
+                    }
+                    Err(_) => false,
+                };
+                if !sent {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+        Ok(WavRecorder {
+            path: path.to_owned(),
+            hook: Some(install_post_mix(hook)),
+            writer: Some(writer),
+            dropped,
+        })
+    }
+
+    /// The file being recorded to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of mixer callbacks left out of the file so far, because
+    /// the writer fell behind or stopped.
+    pub fn dropped_blocks(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stop recording and complete the file, returning the number of sample
+    /// bytes recorded.
+    pub fn finish(mut self) -> Result<u64, MixerError> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<u64, MixerError> {
+        // Removing the hook drops the sender, which ends the writer.
+        drop(self.hook.take());
+        match self.writer.take().map(|writer| writer.join()) {
+            Some(Ok(Ok(len))) => Ok(len),
+            Some(Ok(Err(e))) => Err(save_error(&self.path, e)),
+            Some(Err(_)) => {
+                Err(MixerError::Save {
+                    path: self.path.clone(),
+                    reason: "writer thread panicked".to_owned(),
+                })
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {AudioFormat, AudioSpec};
+    use super::{to_wav_samples, write_header};
+
+    fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    }
+
+    // The chunks of a RIFF/WAVE header, as (id, body).
+    fn chunks(header: &[u8]) -> Vec<(&[u8], &[u8])> {
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(&header[8..12], b"WAVE");
+        let mut chunks = Vec::new();
+        let mut pos = 12;
+        while pos < header.len() {
+            let len = u32_at(header, pos + 4) as usize;
+            let end = (pos + 8 + len).min(header.len());
+            chunks.push((&header[pos..pos + 4], &header[pos + 8..end]));
+            pos += 8 + len;
+        }
+        chunks
+    }
+
+    fn header(format: AudioFormat, channels: isize, data_len: u32) -> Vec<u8> {
+        let spec = AudioSpec {
+            frequency: 44100,
+            format,
+            channels,
+        };
+        let mut header = Vec::new();
+        write_header(&mut header, &spec, data_len).unwrap();
+        header
+    }
+
+    #[test]
+    fn pcm_header_round_trip() {
+        let header = header(AudioFormat::U16MSB, 2, 4000);
+        assert_eq!(header.len(), 44);
+        assert_eq!(u32_at(&header, 4) as usize, header.len() - 8 + 4000);
+        let chunks = chunks(&header);
+        assert_eq!(chunks.len(), 2);
+        let (id, fmt) = chunks[0];
+        assert_eq!((id, fmt.len()), (&b"fmt "[..], 16));
+        assert_eq!((u16_at(fmt, 0), u16_at(fmt, 2)), (1, 2));
+        assert_eq!((u32_at(fmt, 4), u32_at(fmt, 8)), (44100, 44100 * 4));
+        assert_eq!((u16_at(fmt, 12), u16_at(fmt, 14)), (4, 16));
+        assert_eq!(&chunks[1].0, b"data");
+        assert_eq!(u32_at(&header, 40), 4000);
+    }
+
+    #[test]
+    fn float_header_has_the_extended_format_and_fact() {
+        let header = header(AudioFormat::F32LSB, 2, 8000);
+        assert_eq!(header.len(), 58);
+        assert_eq!(u32_at(&header, 4) as usize, header.len() - 8 + 8000);
+        let chunks = chunks(&header);
+        let ids: Vec<_> = chunks.iter().map(|&(id, _)| id).collect();
+        assert_eq!(ids, vec![&b"fmt "[..], b"fact", b"data"]);
+        let fmt = chunks[0].1;
+        assert_eq!(fmt.len(), 18);
+        assert_eq!((u16_at(fmt, 0), u16_at(fmt, 2)), (3, 2));
+        assert_eq!((u16_at(fmt, 12), u16_at(fmt, 14), u16_at(fmt, 16)), (8, 32, 0));
+        assert_eq!(chunks[1].1, &1000u32.to_le_bytes());
+        assert_eq!(u32_at(&header, 54), 8000);
+    }
+
+    #[test]
+    fn converts_samples_to_the_stored_format() {
+        let mut s8 = [0x00, 0x7f, 0x80];
+        to_wav_samples(&mut s8, AudioFormat::S8);
+        assert_eq!(s8, [0x80, 0xff, 0x00]);
+        let mut u16 = [0x80, 0x00, 0x12, 0x34];
+        to_wav_samples(&mut u16, AudioFormat::U16MSB);
+        assert_eq!(u16, [0x00, 0x00, 0x34, 0x92]);
+        let mut s32 = [1, 2, 3, 4];
+        to_wav_samples(&mut s32, AudioFormat::S32MSB);
+        assert_eq!(s32, [4, 3, 2, 1]);
+        let mut s16 = [1, 2, 3, 4];
+        to_wav_samples(&mut s16, AudioFormat::S16LSB);
+        assert_eq!(s16, [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Tests of the mixer wrappers against the fake backend, run with
+//! `cargo test --features testing`.
+
+extern crate sdl2_mixer;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use sdl2_mixer::{AudioSpec, Chunk, EventSink, Fading, MixerError, MixerEvent, Music, Samples,
+                 SynchroChange, SynchroWatcher, WavRecorder, AUDIO_S16SYS};
+use sdl2_mixer::fake::{ChannelEffects, FakeMixer};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+fn open() -> FakeMixer {
+    FakeMixer::open().unwrap()
+}
+
+#[test]
+fn chunk_length_in_the_device_format() {
+    let spec = AudioSpec {
+        frequency: 8000,
+        format: AUDIO_S16SYS,
+        channels: 2,
+    };
+    let _mixer = FakeMixer::open_spec(spec).unwrap();
+    let chunk = Chunk::from_raw_samples(vec![0; 4 * 12_001]).unwrap();
+    assert_eq!(chunk.len_bytes(), 48_004);
+    assert_eq!(chunk.frames().unwrap(), 12_001);
+    assert_eq!(chunk.duration().unwrap(), Duration::new(1, 500_125_000));
+    let samples = chunk.samples().unwrap();
+    assert_eq!((samples.channels(), samples.frame_count()), (2, 12_001));
+    match samples.samples() {
+        Samples::S16(samples) => assert!(samples.len() == 24_002 && samples.iter().all(|&s| s == 0)),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn channel_plays_for_the_length_of_the_chunk() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    let channel = mixer.device().all_channels().play(&chunk, 0).unwrap();
+    assert_eq!(channel, mixer.device().channel(0));
+    mixer.advance(ms(99));
+    assert!(channel.is_playing());
+    mixer.advance(ms(1));
+    assert!(!channel.is_playing());
+    assert!(channel.get_chunk().is_none());
+    assert_eq!(mixer.elapsed(), ms(100));
+}
+
+#[test]
+fn channel_loops() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(50)).unwrap();
+    let channel = mixer.device().all_channels().play(&chunk, 2).unwrap();
+    mixer.advance(ms(149));
+    assert!(channel.is_playing());
+    mixer.advance(ms(1));
+    assert!(!channel.is_playing());
+}
+
+#[test]
+fn paused_channel_stands_still() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    let channel = mixer.device().all_channels().play_timed(&chunk, -1, 80).unwrap();
+    mixer.advance(ms(50));
+    channel.pause();
+    assert!(channel.is_paused() && channel.is_playing());
+    assert_eq!(mixer.device().paused_channels(), 1);
+    mixer.advance(ms(1000));
+    channel.resume();
+    mixer.advance(ms(29));
+    assert!(channel.is_playing());
+    mixer.advance(ms(1));
+    assert!(!channel.is_playing());
+}
+
+#[test]
+fn expire() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    let channel = mixer.device().all_channels().play(&chunk, -1).unwrap();
+    assert_eq!(channel.expire(250), 1);
+    mixer.advance(ms(249));
+    assert!(channel.is_playing());
+    mixer.advance(ms(1));
+    assert!(!channel.is_playing());
+}
+
+#[test]
+fn channel_fades() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(1000)).unwrap();
+    let channel = mixer.device().all_channels().fade_in(&chunk, 0, 100).unwrap();
+    assert_eq!(channel.get_volume(), 0);
+    assert_eq!(channel.get_fading(), Fading::FadingIn);
+    mixer.advance(ms(50));
+    assert_eq!(channel.get_volume(), 64);
+    mixer.advance(ms(50));
+    assert_eq!(channel.get_fading(), Fading::NoFading);
+    assert_eq!(channel.get_volume(), 128);
+
+    assert_eq!(channel.fade_out(200), 1);
+    mixer.advance(ms(150));
+    assert_eq!(channel.get_volume(), 32);
+    mixer.advance(ms(50));
+    assert!(!channel.is_playing());
+    assert_eq!(channel.get_volume(), 128);
+}
+
+#[test]
+fn halt_during_fade_restores_the_volume() {
+    let mixer = open();
+    let chunk = mixer.chunk(ms(1000)).unwrap();
+    let channel = mixer.device().all_channels().fade_in(&chunk, 0, 100).unwrap();
+    mixer.advance(ms(25));
+    channel.halt();
+    assert_eq!(channel.get_fading(), Fading::NoFading);
+    assert_eq!(channel.get_volume(), 128);
+}
+
+#[test]
+fn no_free_channel() {
+    let mixer = open();
+    mixer.device().allocate_channels(2);
+    assert_eq!(mixer.device().reserve_channels(1), 1);
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    assert_eq!(mixer.device().all_channels().play(&chunk, 0).unwrap(), mixer.device().channel(1));
+    assert!(mixer.device().all_channels().play(&chunk, 0).is_err());
+    mixer.device().channel(0).play(&chunk, 0).unwrap();
+    assert_eq!(mixer.device().playing_channels(), 2);
+}
+
+#[test]
+fn finished_callbacks_run_in_order() {
+    let mixer = open();
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let sink = finished.clone();
+    sdl2_mixer::set_channel_finished(Box::new(move |channel| sink.lock().unwrap().push(channel.number())));
+    let short = mixer.chunk(ms(30)).unwrap();
+    let long = mixer.chunk(ms(60)).unwrap();
+    let first = mixer.device().all_channels().play(&long, 0).unwrap();
+    let second = mixer.device().all_channels().play(&short, 0).unwrap();
+    mixer.advance(ms(100));
+    sdl2_mixer::unset_channel_finished();
+    assert_eq!(*finished.lock().unwrap(), vec![second.number(), first.number()]);
+}
+
+#[test]
+fn freeing_the_chunk_waits_for_the_channel() {
+    let mixer = open();
+    let channel = {
+        let chunk = mixer.chunk(ms(100)).unwrap();
+        mixer.device().all_channels().play(&chunk, 0).unwrap()
+    };
+    mixer.advance(ms(50));
+    assert!(channel.is_playing());
+    assert!(channel.get_chunk().is_some());
+    mixer.advance(ms(50));
+    assert!(!channel.is_playing());
+}
+
+#[test]
+fn volume() {
+    let mixer = open();
+    let device = mixer.device();
+    device.channel(0).set_volume(64);
+    assert_eq!(device.channel(0).get_volume(), 64);
+    device.channel(1).set_volume(200);
+    assert_eq!(device.channel(1).get_volume(), 128);
+    assert_eq!(mixer.device().all_channels().get_volume(), (64 + 7 * 128) / 8);
+    let mut chunk = mixer.chunk(ms(10)).unwrap();
+    chunk.set_volume(32);
+    assert_eq!(chunk.get_volume(), 32);
+}
+
+#[test]
+fn groups() {
+    let mixer = open();
+    let device = mixer.device();
+    let group = device.group(1);
+    assert_eq!(group.add_channels_range(2, 4), 3);
+    assert_eq!(group.count(), 3);
+    assert_eq!(group.find_available(), Some(device.channel(2)));
+
+    let chunk = mixer.chunk(ms(500)).unwrap();
+    device.channel(3).play(&chunk, 0).unwrap();
+    mixer.advance(ms(10));
+    device.channel(2).play(&chunk, 0).unwrap();
+    assert_eq!(group.find_available(), Some(device.channel(4)));
+    assert_eq!(group.find_oldest(), Some(device.channel(3)));
+    assert_eq!(group.find_newest(), Some(device.channel(2)));
+
+    assert_eq!(group.fade_out(100), 2);
+    assert_eq!(device.channel(2).get_fading(), Fading::FadingOut);
+    mixer.advance(ms(100));
+    assert_eq!(group.find_oldest(), None);
+    device.channel(0).play(&chunk, 0).unwrap();
+    group.halt();
+    assert!(device.channel(0).is_playing());
+}
+
+#[test]
+fn effects_are_cleared_when_the_channel_finishes() {
+    let mixer = open();
+    let channel = mixer.device().channel(0);
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    channel.play(&chunk, 0).unwrap();
+    channel.set_panning(255, 0).unwrap();
+    channel.set_position(-90, 20).unwrap();
+    channel.set_reverse_stereo(true).unwrap();
+    assert_eq!(mixer.effects(channel),
+               ChannelEffects {
+                   panning: (255, 0),
+                   distance: 20,
+                   angle: 270,
+                   reverse_stereo: true,
+               });
+    channel.unset_panning().unwrap();
+    assert_eq!(mixer.effects(channel).panning, (255, 255));
+    mixer.advance(ms(100));
+    assert_eq!(mixer.effects(channel), ChannelEffects::default());
+    assert!(mixer.device().channel(8).set_distance(10).is_err());
+}
+
+#[test]
+fn custom_effects_run_until_the_channel_finishes() {
+    let mixer = open();
+    let channel = mixer.device().channel(0);
+    let chunk = mixer.chunk(ms(100)).unwrap();
+    let calls = Arc::new(Mutex::new(0));
+    let done = Arc::new(Mutex::new(false));
+    let (count, finished) = (calls.clone(), done.clone());
+    channel.play(&chunk, 0).unwrap();
+    let _effect = channel.register_effect(Box::new(move |_| *count.lock().unwrap() += 1),
+                                          Some(Box::new(move || {
+                                              *finished.lock().unwrap() = true
+                                          })))
+                         .unwrap();
+    mixer.advance(ms(50));
+    assert!(*calls.lock().unwrap() > 0);
+    assert!(!*done.lock().unwrap());
+    mixer.advance(ms(50));
+    assert!(*done.lock().unwrap());
+    let calls_when_finished = *calls.lock().unwrap();
+    mixer.advance(ms(100));
+    assert_eq!(*calls.lock().unwrap(), calls_when_finished);
+}
+
+#[test]
+fn dropping_an_effect_unregisters_it() {
+    let mixer = open();
+    let channel = mixer.device().channel(0);
+    let chunk = mixer.chunk(ms(1000)).unwrap();
+    let calls = Arc::new(Mutex::new(0));
+    let done = Arc::new(Mutex::new(false));
+    let (count, finished) = (calls.clone(), done.clone());
+    channel.play(&chunk, 0).unwrap();
+    let effect = channel.register_effect(Box::new(move |_| *count.lock().unwrap() += 1),
+                                         Some(Box::new(move || *finished.lock().unwrap() = true)))
+                        .unwrap();
+    mixer.advance(ms(50));
+    drop(effect);
+    assert!(*done.lock().unwrap());
+    let calls_when_dropped = *calls.lock().unwrap();
+    mixer.advance(ms(100));
+    assert_eq!(*calls.lock().unwrap(), calls_when_dropped);
+    assert!(channel.is_playing());
+}
+
+#[test]
+fn hooked_music_reaches_the_post_mix() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let heard = Arc::new(Mutex::new(0.0f32));
+    let peak = heard.clone();
+    let _post_mix = sdl2_mixer::set_post_mix_buffer(move |buf| {
+                        let loudest = buf.iter_f32().fold(0.0f32, |max, s| max.max(s.abs()));
+                        let mut peak = peak.lock().unwrap();
+                        *peak = peak.max(loudest);
+                    })
+                        .unwrap();
+    let hook = Music::hook_buffer(|buf| buf.map_f32(|_| 0.5)).unwrap();
+    mixer.advance(ms(50));
+    assert!((*heard.lock().unwrap() - 0.5).abs() < 0.01);
+    player.set_volume(64);
+    *heard.lock().unwrap() = 0.0;
+    mixer.advance(ms(50));
+    assert!((*heard.lock().unwrap() - 0.25).abs() < 0.01);
+    player.set_volume(128);
+    drop(hook);
+    *heard.lock().unwrap() = 0.0;
+    mixer.advance(ms(50));
+    assert_eq!(*heard.lock().unwrap(), 0.0);
+}
+
+#[test]
+fn wav_recorder_fails_to_start_on_a_bad_path() {
+    let _mixer = open();
+    let path = Path::new("/no/such/dir/recording.wav");
+    match WavRecorder::start(path) {
+        Err(MixerError::Save { path: ref failed, .. }) => assert_eq!(failed, path),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn synchro_watcher_follows_the_music() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = SynchroWatcher::new(EventSink::Sender(tx));
+    assert_eq!(watcher.poll(player), SynchroChange::Unchanged);
+    assert!(player.set_synchro_value(5).is_err());
+    let music = mixer.music(ms(100)).unwrap();
+    player.play(&music, 0).unwrap();
+    assert_eq!(watcher.poll(player), SynchroChange::Changed(0));
+    player.set_synchro_value(5).unwrap();
+    assert_eq!(watcher.poll(player), SynchroChange::Changed(5));
+    assert_eq!(watcher.poll(player), SynchroChange::Unchanged);
+    mixer.advance(ms(100));
+    assert_eq!(watcher.poll(player), SynchroChange::Unsupported);
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(),
+               vec![MixerEvent::SynchroChanged(0), MixerEvent::SynchroChanged(5)]);
+}
+
+#[test]
+fn soundfonts_and_decoders() {
+    let _mixer = open();
+    assert_eq!(sdl2_mixer::get_chunk_decoders_number(), 1);
+    assert_eq!(sdl2_mixer::get_chunk_decoder(0), "WAVE");
+    assert_eq!(sdl2_mixer::get_music_decoder(1), "");
+    let dir = env::temp_dir();
+    let first = dir.join(format!("sdl2_mixer_fake_{}_a.sf2", process::id()));
+    let second = dir.join(format!("sdl2_mixer_fake_{}_b.sf2", process::id()));
+    fs::write(&first, b"").unwrap();
+    fs::write(&second, b"").unwrap();
+    assert!(sdl2_mixer::set_soundfonts(&[Path::new("missing.sf2")]).is_err());
+    sdl2_mixer::set_soundfonts(&[&first, &second]).unwrap();
+    assert_eq!(sdl2_mixer::get_soundfonts(), vec![first.clone(), second.clone()]);
+    let mut seen = Vec::new();
+    assert!(sdl2_mixer::each_soundfont(|path| {
+        seen.push(path.to_owned());
+        true
+    }));
+    assert_eq!(seen, vec![first.clone(), second.clone()]);
+    assert!(!sdl2_mixer::each_soundfont(|_| false));
+    sdl2_mixer::set_soundfonts(&[]).unwrap();
+    assert!(sdl2_mixer::get_soundfonts().is_empty());
+    assert!(!sdl2_mixer::each_soundfont(|_| true));
+    fs::remove_file(&first).unwrap();
+    fs::remove_file(&second).unwrap();
+}
+
+#[test]
+fn music_plays_its_length_and_loops() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let ended = Arc::new(Mutex::new(0));
+    let count = ended.clone();
+    Music::hook_finished(Box::new(move || *count.lock().unwrap() += 1));
+    let music = mixer.music(ms(100)).unwrap();
+    player.play(&music, 3).unwrap();
+    mixer.advance(ms(399));
+    assert!(player.is_playing());
+    mixer.advance(ms(1));
+    assert!(!player.is_playing());
+    Music::unhook_finished();
+    assert_eq!(*ended.lock().unwrap(), 1);
+}
+
+#[test]
+fn paused_music_stands_still() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let music = mixer.music(ms(100)).unwrap();
+    player.play(&music, 0).unwrap();
+    mixer.advance(ms(40));
+    player.pause();
+    assert!(player.is_paused() && player.is_playing());
+    mixer.advance(ms(500));
+    player.resume();
+    mixer.advance(ms(59));
+    assert!(player.is_playing());
+    player.rewind();
+    mixer.advance(ms(99));
+    assert!(player.is_playing());
+    mixer.advance(ms(1));
+    assert!(!player.is_playing());
+}
+
+#[test]
+fn music_fades() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let music = mixer.music(ms(1000)).unwrap();
+    player.fade_in(&music, 0, 100).unwrap();
+    assert_eq!(player.get_fading(), Fading::FadingIn);
+    mixer.advance(ms(100));
+    assert_eq!(player.get_fading(), Fading::NoFading);
+    player.fade_out(200).unwrap();
+    assert_eq!(player.get_fading(), Fading::FadingOut);
+    mixer.advance(ms(199));
+    assert!(player.is_playing());
+    mixer.advance(ms(1));
+    assert!(!player.is_playing());
+}
+
+#[test]
+fn new_music_replaces_the_old() {
+    let mixer = open();
+    let player = mixer.device().music();
+    let first = mixer.music(ms(100)).unwrap();
+    let second = mixer.music(ms(300)).unwrap();
+    player.play(&first, 0).unwrap();
+    mixer.advance(ms(50));
+    player.play(&second, 0).unwrap();
+    drop(second);
+    mixer.advance(ms(299));
+    assert!(player.is_playing());
+    player.set_volume(32);
+    assert_eq!(player.get_volume(), 32);
+    mixer.advance(ms(1));
+    assert!(!player.is_playing());
+}
@@ -0,0 +1,301 @@
+//! Tests of the mixer on a headless audio driver, run with
+//! `cargo test --features testing`.
+//!
+//! Fades, timed playback and the order of channels in a group follow
+//! SDL_GetTicks rather than the mixed audio, so their tests wait on the wall
+//! clock and can only fail late on a loaded machine. They are ignored by
+//! default; run them with `cargo test --features testing -- --ignored`.
+
+extern crate sdl2;
+extern crate sdl2_mixer;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use sdl2::rwops::RWops;
+use sdl2_mixer::{Fading, LoaderRWops};
+use sdl2_mixer::testing::{Driver, Harness};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+// How long to wait for what SDL_mixer times by the wall clock, which a
+// loaded machine can only make later.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn open() -> Harness {
+    Harness::open(Driver::Disk).unwrap()
+}
+
+fn peak(harness: &Harness) -> f32 {
+    let output = harness.take_output_chunk().unwrap();
+    let samples = output.samples().unwrap();
+    samples.iter_f32().fold(0.0, |max, sample| max.max(sample.abs()))
+}
+
+#[test]
+fn channel_plays_to_the_end() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(100)).unwrap();
+    let channel = harness.device().all_channels().play(&tone, 0).unwrap();
+    harness.advance(ms(50));
+    harness.assert_playing(channel);
+    assert!(channel.get_chunk() == Some(tone));
+    harness.advance(ms(100));
+    harness.assert_stopped(channel);
+    assert!(channel.get_chunk().is_none());
+}
+
+#[test]
+fn channel_loops() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(50)).unwrap();
+    let channel = harness.device().all_channels().play(&tone, 2).unwrap();
+    harness.advance(ms(120));
+    harness.assert_playing(channel);
+    harness.advance(ms(60));
+    harness.assert_stopped(channel);
+}
+
+#[test]
+fn channel_pause_and_resume() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(100)).unwrap();
+    let channel = harness.device().all_channels().play(&tone, 0).unwrap();
+    channel.pause();
+    harness.assert_paused(channel);
+    assert_eq!(harness.device().paused_channels(), 1);
+    harness.advance(ms(200));
+    harness.assert_paused(channel);
+    channel.resume();
+    harness.assert_playing(channel);
+    harness.advance(ms(150));
+    harness.assert_stopped(channel);
+}
+
+#[test]
+fn channel_halt() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(500)).unwrap();
+    let channel = harness.device().all_channels().play(&tone, -1).unwrap();
+    harness.advance(ms(20));
+    channel.halt();
+    harness.assert_stopped(channel);
+    assert_eq!(harness.device().playing_channels(), 0);
+}
+
+#[test]
+#[ignore = "waits on the wall clock"]
+fn channel_fades() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(100)).unwrap();
+    let channel = harness.device().all_channels().fade_in(&tone, -1, 100).unwrap();
+    harness.assert_fading(channel, Fading::FadingIn);
+    harness.advance_until(TIMEOUT, || channel.get_fading() == Fading::NoFading);
+    harness.assert_playing(channel);
+    channel.fade_out(100);
+    harness.assert_fading(channel, Fading::FadingOut);
+    harness.advance_until(TIMEOUT, || !channel.is_playing());
+}
+
+#[test]
+#[ignore = "waits on the wall clock"]
+fn channel_timed_playback() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(1000)).unwrap();
+    let start = Instant::now();
+    let channel = harness.device().all_channels().play_timed(&tone, -1, 100).unwrap();
+    harness.assert_playing(channel);
+    harness.advance_until(TIMEOUT, || !channel.is_playing());
+    // SDL_GetTicks counts whole milliseconds.
+    assert!(start.elapsed() >= ms(99), "stopped after {:?}", start.elapsed());
+}
+
+#[test]
+fn channel_volume() {
+    let harness = open();
+    let tone = harness.tone(440.0, ms(200)).unwrap();
+    let channel = harness.device().channel(0);
+    channel.set_volume(128);
+    assert_eq!(channel.get_volume(), 128);
+    channel.play(&tone, 0).unwrap();
+    harness.advance(ms(50));
+    let loud = peak(&harness);
+    channel.set_volume(32);
+    harness.take_output();
+    harness.advance(ms(50));
+    let quiet = peak(&harness);
+    assert!(loud > 0.4, "peak {}", loud);
+    assert!(quiet < loud / 2.0, "peak {} after {}", quiet, loud);
+}
+
+#[test]
+fn silence_without_playing() {
+    let harness = open();
+    harness.advance(ms(50));
+    assert_eq!(peak(&harness), 0.0);
+}
+
+#[test]
+fn no_free_channel() {
+    let harness = open();
+    harness.device().allocate_channels(2);
+    let tone = harness.tone(440.0, ms(500)).unwrap();
+    harness.device().all_channels().play(&tone, 0).unwrap();
+    harness.device().all_channels().play(&tone, 0).unwrap();
+    assert!(harness.device().all_channels().play(&tone, 0).is_err());
+}
+
+#[test]
+fn reserved_channels_are_skipped() {
+    let harness = open();
+    assert_eq!(harness.device().reserve_channels(2), 2);
+    let tone = harness.tone(440.0, ms(500)).unwrap();
+    let channel = harness.device().all_channels().play(&tone, 0).unwrap();
+    assert_eq!(channel, harness.device().channel(2));
+    harness.device().reserve_channels(0);
+}
+
+#[test]
+#[ignore = "waits on the wall clock"]
+fn groups() {
+    let harness = open();
+    let device = harness.device();
+    let group = device.group(1);
+    assert_eq!(group.add_channels_range(0, 1), 2);
+    assert_eq!(group.count(), 2);
+    assert_eq!(group.find_available(), Some(device.channel(0)));
+
+    let tone = harness.tone(440.0, ms(500)).unwrap();
+    device.channel(0).play(&tone, 0).unwrap();
+    // Channels are ordered by the SDL_GetTicks they started at.
+    thread::sleep(ms(20));
+    device.channel(1).play(&tone, 0).unwrap();
+    assert_eq!(group.find_available(), None);
+    assert_eq!(group.find_oldest(), Some(device.channel(0)));
+    assert_eq!(group.find_newest(), Some(device.channel(1)));
+
+    group.halt();
+    harness.assert_stopped(device.channel(0));
+    harness.assert_stopped(device.channel(1));
+    device.group(-1).add_channels_range(0, 1);
+    assert_eq!(group.count(), 0);
+}
+
+#[test]
+#[ignore = "waits on the wall clock"]
+fn group_fade_out() {
+    let harness = open();
+    let device = harness.device();
+    let group = device.group(2);
+    assert!(group.add_channel(device.channel(3)));
+    let tone = harness.tone(440.0, ms(1000)).unwrap();
+    device.channel(3).play(&tone, 0).unwrap();
+    assert_eq!(group.fade_out(100), 1);
+    harness.assert_fading(device.channel(3), Fading::FadingOut);
+    harness.advance_until(TIMEOUT, || !device.channel(3).is_playing());
+    device.group(-1).add_channel(device.channel(3));
+}
+
+#[test]
+fn music_plays_to_the_end() {
+    let harness = open();
+    let player = harness.device().music();
+    let music = harness.tone_music(220.0, ms(100)).unwrap();
+    player.play(&music, 0).unwrap();
+    harness.advance(ms(50));
+    harness.assert_music_playing();
+    assert!(peak(&harness) > 0.0);
+    harness.advance(ms(100));
+    harness.assert_music_stopped();
+}
+
+#[test]
+fn music_pause_and_volume() {
+    let harness = open();
+    let player = harness.device().music();
+    let music = harness.tone_music(220.0, ms(500)).unwrap();
+    player.play(&music, -1).unwrap();
+    player.set_volume(64);
+    assert_eq!(player.get_volume(), 64);
+    player.pause();
+    assert!(player.is_paused());
+    harness.take_output();
+    harness.advance(ms(50));
+    assert_eq!(peak(&harness), 0.0);
+    player.resume();
+    harness.assert_music_playing();
+    player.halt();
+    harness.assert_music_stopped();
+    player.set_volume(128);
+}
+
+#[test]
+fn music_fades() {
+    let harness = open();
+    let player = harness.device().music();
+    let music = harness.tone_music(220.0, ms(1000)).unwrap();
+    player.fade_in(&music, 0, 100).unwrap();
+    harness.assert_music_fading(Fading::FadingIn);
+    harness.advance(ms(150));
+    harness.assert_music_fading(Fading::NoFading);
+    player.fade_out(100).unwrap();
+    harness.assert_music_fading(Fading::FadingOut);
+    harness.advance(ms(150));
+    harness.assert_music_stopped();
+}
+
+#[test]
+fn music_from_a_borrowed_source_halts_when_dropped() {
+    let harness = open();
+    let player = harness.device().music();
+    let wav = harness.tone_wav(220.0, ms(100));
+    let src = RWops::from_bytes(&wav).unwrap();
+    let music = src.load_music().unwrap();
+    player.play(&music, -1).unwrap();
+    harness.assert_music_playing();
+    drop(music);
+    harness.assert_music_stopped();
+}
+
+#[test]
+fn effect_setters() {
+    let harness = open();
+    let channel = harness.device().channel(0);
+    let tone = harness.tone(440.0, ms(200)).unwrap();
+    channel.play(&tone, 0).unwrap();
+    channel.set_panning(255, 0).unwrap();
+    channel.set_distance(100).unwrap();
+    channel.set_position(90, 0).unwrap();
+    channel.set_reverse_stereo(true).unwrap();
+    harness.advance(ms(20));
+    channel.unset_panning().unwrap();
+    channel.unset_distance().unwrap();
+    channel.unset_position().unwrap();
+    channel.set_reverse_stereo(false).unwrap();
+    channel.unregister_all_effects().unwrap();
+    assert!(harness.device().post_channel().set_panning(128, 128).is_ok());
+    harness.device().post_channel().unregister_all_effects().unwrap();
+}
+
+#[test]
+fn panning_moves_the_sound() {
+    let harness = open();
+    if harness.spec().channels != 2 {
+        return;
+    }
+    let channel = harness.device().channel(0);
+    let tone = harness.tone(440.0, ms(200)).unwrap();
+    channel.set_panning(255, 0).unwrap();
+    channel.play(&tone, 0).unwrap();
+    harness.advance(ms(50));
+    let output = harness.take_output_chunk().unwrap();
+    let samples = output.samples().unwrap();
+    let (mut left, mut right) = (0.0f32, 0.0f32);
+    for frame in samples.frames() {
+        left = left.max(frame.get(0).abs());
+        right = right.max(frame.get(1).abs());
+    }
+    assert!(left > 0.0 && right == 0.0, "left {}, right {}", left, right);
+    channel.unset_panning().unwrap();
+}